@@ -0,0 +1,323 @@
+//! Bookkeeping for user breakpoints. Several breakpoints may share an address; the 0xcc is in
+//! the inferior as long as at least one of them is enabled. Internal breakpoints, which deet
+//! sets for its own purposes, keep a trap in place too.
+
+use crate::dwarf_data::Line;
+use crate::expr::Expr;
+use crate::inferior::Inferior;
+use std::collections::HashMap;
+use std::fmt;
+
+pub struct Breakpoint {
+    pub id: usize,
+    pub addr: usize,
+    /// The location as the user gave it, e.g. "main" or "12". Used to find the breakpoint again
+    /// when the inferior execs a new program.
+    pub location: String,
+    /// True while the location can't be found, e.g. because it is in a shared library that
+    /// hasn't been loaded yet. `addr` means nothing until then.
+    pub pending: bool,
+    pub function: Option<String>,
+    pub line: Option<Line>,
+    pub enabled: bool,
+    pub hit_count: usize,
+    pub condition: Option<Condition>,
+    /// Number of upcoming hits to pass over without stopping.
+    pub ignore_count: usize,
+}
+
+#[derive(Clone)]
+pub struct Condition {
+    pub source: String,
+    pub expr: Expr,
+}
+
+impl fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.pending {
+            return write!(f, "<PENDING> {}", self.location);
+        }
+        write!(f, "{:#x}", self.addr)?;
+        if let Some(function) = &self.function {
+            write!(f, " in {}", function)?;
+        }
+        if let Some(line) = &self.line {
+            write!(f, " at {}", line)?;
+        }
+        Ok(())
+    }
+}
+
+pub struct BreakpointTable {
+    breakpoints: Vec<Breakpoint>,
+    next_id: usize,
+    /// Addresses that currently hold a 0xcc, mapped to the original byte underneath. Before the
+    /// inferior starts, the original byte is unknown and recorded as 0.
+    inserted: HashMap<usize, u8>,
+    /// Addresses of internal breakpoints.
+    internal: Vec<usize>,
+}
+
+impl BreakpointTable {
+    pub fn new() -> BreakpointTable {
+        BreakpointTable {
+            breakpoints: Vec::new(),
+            next_id: 1,
+            inserted: HashMap::new(),
+            internal: Vec::new(),
+        }
+    }
+
+    /// Returns the trap addresses and original bytes, as used by the stepping code in
+    /// `Inferior`.
+    pub fn inserted(&self) -> &HashMap<usize, u8> {
+        &self.inserted
+    }
+
+    pub fn inserted_mut(&mut self) -> &mut HashMap<usize, u8> {
+        &mut self.inserted
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Breakpoint> {
+        self.breakpoints.iter()
+    }
+
+    pub fn get(&self, id: usize) -> Option<&Breakpoint> {
+        self.breakpoints.iter().find(|bp| bp.id == id)
+    }
+
+    /// Hands out the next breakpoint number. Watchpoints are numbered from the same sequence.
+    pub fn allocate_id(&mut self) -> usize {
+        let id = self.next_id;
+        self.next_id += 1;
+        id
+    }
+
+    /// Adds a new enabled breakpoint, inserting its trap into the inferior if one is running.
+    /// Returns the ID of the new breakpoint.
+    pub fn add(
+        &mut self,
+        addr: usize,
+        location: String,
+        function: Option<String>,
+        line: Option<Line>,
+        condition: Option<Condition>,
+        inferior: Option<&mut Inferior>,
+    ) -> Result<usize, nix::Error> {
+        if !self.inserted.contains_key(&addr) {
+            let orig_byte = match inferior {
+                Some(inferior) => inferior.write_byte(addr, 0xcc)?,
+                None => 0,
+            };
+            self.inserted.insert(addr, orig_byte);
+        }
+        let id = self.allocate_id();
+        self.breakpoints.push(Breakpoint {
+            id,
+            addr,
+            location,
+            pending: false,
+            function,
+            line,
+            enabled: true,
+            hit_count: 0,
+            condition,
+            ignore_count: 0,
+        });
+        Ok(id)
+    }
+
+    /// Adds a breakpoint on a location that can't be found yet. Returns the ID of the new
+    /// breakpoint.
+    pub fn add_pending(&mut self, location: String, condition: Option<Condition>) -> usize {
+        let id = self.allocate_id();
+        self.breakpoints.push(Breakpoint {
+            id,
+            addr: 0,
+            location,
+            pending: true,
+            function: None,
+            line: None,
+            enabled: true,
+            hit_count: 0,
+            condition,
+            ignore_count: 0,
+        });
+        id
+    }
+
+    /// Adds an internal breakpoint at `addr`.
+    pub fn add_internal(&mut self, addr: usize, inferior: &mut Inferior) -> Result<(), nix::Error> {
+        self.internal.push(addr);
+        self.sync_trap(addr, Some(inferior))
+    }
+
+    /// Deletes the internal breakpoint at `addr`, removing its trap unless something else needs
+    /// it.
+    pub fn delete_internal(
+        &mut self,
+        addr: usize,
+        inferior: &mut Inferior,
+    ) -> Result<(), nix::Error> {
+        if let Some(index) = self.internal.iter().position(|internal| *internal == addr) {
+            self.internal.remove(index);
+        }
+        self.sync_trap(addr, Some(inferior))
+    }
+
+    /// Deletes a breakpoint, removing its trap from the inferior unless another enabled
+    /// breakpoint shares the address.
+    pub fn delete(&mut self, id: usize, inferior: Option<&mut Inferior>) -> Result<(), nix::Error> {
+        if let Some(index) = self.breakpoints.iter().position(|bp| bp.id == id) {
+            let addr = self.breakpoints.remove(index).addr;
+            self.sync_trap(addr, inferior)?;
+        }
+        Ok(())
+    }
+
+    /// Deletes every breakpoint.
+    pub fn clear(&mut self, mut inferior: Option<&mut Inferior>) -> Result<(), nix::Error> {
+        let ids: Vec<usize> = self.breakpoints.iter().map(|bp| bp.id).collect();
+        for id in ids {
+            self.delete(id, inferior.as_deref_mut())?;
+        }
+        Ok(())
+    }
+
+    pub fn set_enabled(
+        &mut self,
+        id: usize,
+        enabled: bool,
+        inferior: Option<&mut Inferior>,
+    ) -> Result<(), nix::Error> {
+        if let Some(bp) = self.breakpoints.iter_mut().find(|bp| bp.id == id) {
+            bp.enabled = enabled;
+            let addr = bp.addr;
+            self.sync_trap(addr, inferior)?;
+        }
+        Ok(())
+    }
+
+    /// Inserts or removes the trap at `addr` so that it is present exactly when an enabled
+    /// breakpoint needs it.
+    fn sync_trap(
+        &mut self,
+        addr: usize,
+        inferior: Option<&mut Inferior>,
+    ) -> Result<(), nix::Error> {
+        let wanted = self.internal.contains(&addr)
+            || self
+                .breakpoints
+                .iter()
+                .any(|bp| bp.addr == addr && bp.enabled && !bp.pending);
+        match (wanted, self.inserted.get(&addr).cloned(), inferior) {
+            (true, None, Some(inferior)) => {
+                let orig_byte = inferior.write_byte(addr, 0xcc)?;
+                self.inserted.insert(addr, orig_byte);
+            }
+            (true, None, None) => {
+                self.inserted.insert(addr, 0);
+            }
+            (false, Some(orig_byte), Some(inferior)) => {
+                inferior.write_byte(addr, orig_byte)?;
+                self.inserted.remove(&addr);
+            }
+            (false, Some(_), None) => {
+                self.inserted.remove(&addr);
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Re-resolves every breakpoint from its location for a new program image, none of whose
+    /// memory holds our traps: after the inferior has exec'd, or before a new process starts.
+    /// Internal breakpoints are dropped. `resolve` maps a location to an address, function and
+    /// line. Breakpoints that no longer resolve become pending, and their IDs are returned.
+    pub fn relocate<F>(&mut self, resolve: F) -> Vec<usize>
+    where
+        F: Fn(&str) -> Option<(usize, Option<String>, Option<Line>)>,
+    {
+        let mut unresolved = Vec::new();
+        self.inserted.clear();
+        self.internal.clear();
+        for bp in self.breakpoints.iter_mut() {
+            match resolve(&bp.location) {
+                Some((addr, function, line)) => {
+                    bp.addr = addr;
+                    bp.function = function;
+                    bp.line = line;
+                    bp.pending = false;
+                }
+                None => {
+                    if !bp.pending {
+                        unresolved.push(bp.id);
+                    }
+                    bp.pending = true;
+                }
+            }
+            if bp.enabled && !bp.pending {
+                self.inserted.insert(bp.addr, 0);
+            }
+        }
+        unresolved
+    }
+
+    /// Tries to find the locations of pending breakpoints, e.g. after a shared library has been
+    /// loaded, inserting the traps of those found. Returns the IDs of the breakpoints resolved.
+    pub fn resolve_pending<F>(
+        &mut self,
+        resolve: F,
+        inferior: &mut Inferior,
+    ) -> Result<Vec<usize>, nix::Error>
+    where
+        F: Fn(&str) -> Option<(usize, Option<String>, Option<Line>)>,
+    {
+        let mut resolved = Vec::new();
+        for bp in self.breakpoints.iter_mut().filter(|bp| bp.pending) {
+            if let Some((addr, function, line)) = resolve(&bp.location) {
+                bp.addr = addr;
+                bp.function = function;
+                bp.line = line;
+                bp.pending = false;
+                resolved.push((bp.id, addr));
+            }
+        }
+        for (_, addr) in resolved.iter() {
+            self.sync_trap(*addr, Some(&mut *inferior))?;
+        }
+        Ok(resolved.into_iter().map(|(id, _)| id).collect())
+    }
+
+    pub fn set_ignore_count(&mut self, id: usize, count: usize) {
+        if let Some(bp) = self.breakpoints.iter_mut().find(|bp| bp.id == id) {
+            bp.ignore_count = count;
+        }
+    }
+
+    /// Returns the IDs and conditions of the enabled breakpoints at `addr`.
+    pub fn enabled_at(&self, addr: usize) -> Vec<(usize, Option<Condition>)> {
+        self.breakpoints
+            .iter()
+            .filter(|bp| bp.addr == addr && bp.enabled && !bp.pending)
+            .map(|bp| (bp.id, bp.condition.clone()))
+            .collect()
+    }
+
+    /// Records a hit on a breakpoint whose condition held. Returns false if the hit is being
+    /// ignored.
+    pub fn record_hit(&mut self, id: usize) -> bool {
+        match self.breakpoints.iter_mut().find(|bp| bp.id == id) {
+            Some(bp) => {
+                bp.hit_count += 1;
+                if bp.ignore_count > 0 {
+                    bp.ignore_count -= 1;
+                    false
+                } else {
+                    true
+                }
+            }
+            None => false,
+        }
+    }
+}
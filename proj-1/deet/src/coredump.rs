@@ -0,0 +1,704 @@
+//! Core dumps: ELF files holding a snapshot of a process's memory in PT_LOAD segments and the
+//! state of its threads in PT_NOTE notes. We read them for post-mortem debugging, and write
+//! them in the layout the kernel uses so that other debuggers can read ours.
+
+use crate::memory_map::{self, Mapping};
+use crate::target::Target;
+use nix::errno::Errno;
+use nix::sys::signal::Signal;
+use nix::unistd::Pid;
+use std::convert::TryInto;
+use std::fs;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::os::unix::fs::{FileExt, MetadataExt};
+
+const ET_CORE: u16 = 4;
+const EM_X86_64: u16 = 62;
+const ELF_HEADER_SIZE: usize = 64;
+const PROGRAM_HEADER_SIZE: usize = 56;
+const PAGE_SIZE: usize = 4096;
+/// Memory is copied into a dump this much at a time.
+const COPY_CHUNK_SIZE: usize = 256 * PAGE_SIZE;
+const PT_LOAD: u32 = 1;
+const PT_NOTE: u32 = 4;
+const PF_X: u32 = 1;
+const PF_W: u32 = 2;
+const PF_R: u32 = 4;
+
+const NT_PRSTATUS: u32 = 1;
+const NT_PRPSINFO: u32 = 3;
+const NT_AUXV: u32 = 6;
+const NT_FILE: u32 = 0x4649_4c45;
+
+/// Size of, and offsets into, the x86-64 `struct elf_prstatus`.
+const PRSTATUS_SIZE: usize = 336;
+const PRSTATUS_CURSIG: usize = 12;
+const PRSTATUS_PID: usize = 32;
+const PRSTATUS_REGS: usize = 112;
+/// Size of, and offsets into, the x86-64 `struct elf_prpsinfo`.
+const PRPSINFO_SIZE: usize = 136;
+const PRPSINFO_SNAME: usize = 1;
+const PRPSINFO_UID: usize = 16;
+const PRPSINFO_PID: usize = 24;
+const PRPSINFO_FNAME: usize = 40;
+const FNAME_LEN: usize = 16;
+const PRPSINFO_PSARGS: usize = 56;
+const PSARGS_LEN: usize = 80;
+
+/// A memory segment saved in the dump.
+struct Segment {
+    start: usize,
+    /// Size of the segment in the process, which may be more than was saved.
+    size: usize,
+    flags: u32,
+    data: Vec<u8>,
+}
+
+/// A file that was mapped into the process, from the NT_FILE note.
+struct MappedFile {
+    start: usize,
+    end: usize,
+    offset: usize,
+    path: String,
+}
+
+pub struct Core {
+    /// Thread IDs and registers, starting with the thread that dumped.
+    threads: Vec<(i32, libc::user_regs_struct)>,
+    /// Number of the signal that killed the process.
+    signal: i32,
+    /// Command line of the process, as far as it fit in the dump.
+    command: String,
+    auxv: Vec<u8>,
+    segments: Vec<Segment>,
+    files: Vec<MappedFile>,
+}
+
+impl Core {
+    pub fn open(path: &str) -> Result<Core, String> {
+        let contents = fs::read(path).map_err(|err| err.to_string())?;
+        let malformed = || "not a valid core file".to_string();
+        if contents.get(..6) != Some(&b"\x7fELF\x02\x01"[..]) {
+            return Err("not a 64-bit little-endian ELF file".to_string());
+        }
+        if read_u16(&contents, 16).ok_or_else(malformed)? != ET_CORE {
+            return Err("not a core file".to_string());
+        }
+        let phoff = read_u64(&contents, 32).ok_or_else(malformed)? as usize;
+        let phentsize = read_u16(&contents, 54).ok_or_else(malformed)? as usize;
+        let phnum = read_u16(&contents, 56).ok_or_else(malformed)? as usize;
+
+        let mut core = Core {
+            threads: Vec::new(),
+            signal: 0,
+            command: String::new(),
+            auxv: Vec::new(),
+            segments: Vec::new(),
+            files: Vec::new(),
+        };
+        for index in 0..phnum {
+            let header = index
+                .checked_mul(phentsize)
+                .and_then(|offset| offset.checked_add(phoff))
+                .ok_or_else(malformed)?;
+            let p_type = read_u32(&contents, header).ok_or_else(malformed)?;
+            // The header is in bounds, so offsets within it can't overflow
+            let flags = read_u32(&contents, header + 4).ok_or_else(malformed)?;
+            let offset = read_u64(&contents, header + 8).ok_or_else(malformed)? as usize;
+            let vaddr = read_u64(&contents, header + 16).ok_or_else(malformed)? as usize;
+            let filesz = read_u64(&contents, header + 32).ok_or_else(malformed)? as usize;
+            let memsz = read_u64(&contents, header + 40).ok_or_else(malformed)? as usize;
+            let end = offset.checked_add(filesz).ok_or_else(malformed)?;
+            let data = contents.get(offset..end).ok_or_else(malformed)?.to_vec();
+            // Reads check addresses against the end of the segment
+            vaddr.checked_add(memsz.max(filesz)).ok_or_else(malformed)?;
+            match p_type {
+                PT_LOAD => core.segments.push(Segment {
+                    start: vaddr,
+                    size: memsz,
+                    flags,
+                    data,
+                }),
+                PT_NOTE => core.read_notes(&data).ok_or_else(malformed)?,
+                _ => {}
+            }
+        }
+        if core.threads.is_empty() {
+            return Err("no thread state in core file".to_string());
+        }
+        Ok(core)
+    }
+
+    /// Parses a PT_NOTE segment: a sequence of notes, each a header of name size, descriptor
+    /// size and type, followed by the name and the descriptor padded to 4 bytes.
+    fn read_notes(&mut self, notes: &[u8]) -> Option<()> {
+        let align = |len: usize| (len + 3) & !3;
+        let mut offset = 0;
+        while offset + 12 <= notes.len() {
+            let namesz = read_u32(notes, offset)? as usize;
+            let descsz = read_u32(notes, offset + 4)? as usize;
+            let note_type = read_u32(notes, offset + 8)?;
+            let name = notes.get(offset + 12..offset + 12 + namesz)?;
+            let desc_start = offset + 12 + align(namesz);
+            let desc = notes.get(desc_start..desc_start + descsz)?;
+            offset = desc_start + align(descsz);
+            // Other owners, like "LINUX", reuse the same type numbers
+            if name.split(|byte| *byte == 0).next() != Some(&b"CORE"[..]) {
+                continue;
+            }
+            match note_type {
+                NT_PRSTATUS => {
+                    if self.threads.is_empty() {
+                        self.signal = read_u16(desc, PRSTATUS_CURSIG)? as i32;
+                    }
+                    let tid = read_u32(desc, PRSTATUS_PID)? as i32;
+                    self.threads
+                        .push((tid, parse_registers(desc.get(PRSTATUS_REGS..)?)?));
+                }
+                NT_PRPSINFO => {
+                    let psargs = desc.get(PRPSINFO_PSARGS..PRPSINFO_PSARGS + PSARGS_LEN)?;
+                    let len = psargs
+                        .iter()
+                        .position(|byte| *byte == 0)
+                        .unwrap_or(PSARGS_LEN);
+                    self.command = String::from_utf8_lossy(&psargs[..len]).trim().to_string();
+                }
+                NT_AUXV => self.auxv = desc.to_vec(),
+                NT_FILE => self.files = parse_file_note(desc)?,
+                _ => {}
+            }
+        }
+        Some(())
+    }
+
+    /// Returns the signal that killed the process, if it was one nix knows about.
+    pub fn signal(&self) -> Option<Signal> {
+        Signal::from_c_int(self.signal).ok()
+    }
+
+    /// Returns the ID of the thread that dumped.
+    pub fn current_thread_id(&self) -> i32 {
+        self.threads[0].0
+    }
+
+    pub fn command(&self) -> &str {
+        &self.command
+    }
+
+    /// Returns entry `key` of the process's auxiliary vector.
+    pub fn auxv(&self, key: u64) -> Option<usize> {
+        memory_map::find_auxv(&self.auxv, key)
+    }
+
+    /// Returns the files the process had mapped, in the form /proc/<pid>/maps would have
+    /// listed them.
+    pub fn mappings(&self) -> Vec<Mapping> {
+        self.files
+            .iter()
+            .map(|file| {
+                let flags = self
+                    .segments
+                    .iter()
+                    .find(|segment| segment.start == file.start)
+                    .map_or(PF_R, |segment| segment.flags);
+                Mapping {
+                    start: file.start,
+                    end: file.end,
+                    readable: flags & PF_R != 0,
+                    writable: flags & PF_W != 0,
+                    executable: flags & PF_X != 0,
+                    offset: file.offset,
+                    path: Some(file.path.clone()),
+                }
+            })
+            .collect()
+    }
+
+    /// Reads memory starting at `addr`, returning as much as one segment or mapped file can
+    /// provide, up to `len` bytes.
+    fn read_chunk(&self, addr: usize, len: usize) -> Option<Vec<u8>> {
+        if let Some(segment) = self
+            .segments
+            .iter()
+            .find(|segment| segment.start <= addr && addr < segment.start + segment.data.len())
+        {
+            let offset = addr - segment.start;
+            let end = (offset + len).min(segment.data.len());
+            return Some(segment.data[offset..end].to_vec());
+        }
+        // The kernel leaves out memory that is unchanged from a mapped file, such as code, so
+        // read that from the file
+        let file = self
+            .files
+            .iter()
+            .find(|file| file.start <= addr && addr < file.end)?;
+        let mut bytes = vec![0; len.min(file.end - addr)];
+        let handle = fs::File::open(&file.path).ok()?;
+        let file_offset = file.offset.checked_add(addr - file.start)?;
+        let read = handle.read_at(&mut bytes, file_offset as u64).ok()?;
+        if read == 0 {
+            return None;
+        }
+        bytes.truncate(read);
+        Some(bytes)
+    }
+
+    /// Returns true if `addr` was mapped in the process, even if its contents weren't saved.
+    fn is_mapped(&self, addr: usize) -> bool {
+        self.segments
+            .iter()
+            .any(|segment| segment.start <= addr && addr < segment.start + segment.size)
+    }
+}
+
+impl Target for Core {
+    fn registers(&self) -> Result<libc::user_regs_struct, nix::Error> {
+        Ok(self.threads[0].1)
+    }
+
+    fn read_memory(&self, addr: usize, len: usize) -> Result<Vec<u8>, nix::Error> {
+        // A range that wraps around the address space can't all be mapped
+        addr.checked_add(len)
+            .ok_or(nix::Error::Sys(Errno::EFAULT))?;
+        let mut bytes = Vec::with_capacity(len);
+        while bytes.len() < len {
+            let curr_addr = addr + bytes.len();
+            match self.read_chunk(curr_addr, len - bytes.len()) {
+                Some(chunk) => bytes.extend(chunk),
+                // Mapped, but neither saved in the dump nor backed by a file we can read
+                None if self.is_mapped(curr_addr) => return Err(nix::Error::Sys(Errno::EIO)),
+                None => return Err(nix::Error::Sys(Errno::EFAULT)),
+            }
+        }
+        Ok(bytes)
+    }
+}
+
+/// Writes a core file of process `pid` to `path`: the registers of `threads`, the current
+/// thread first, and the contents of every readable mapping. `read_memory` reads memory as
+/// the program sees it, without any breakpoint traps.
+pub fn write(
+    path: &str,
+    pid: Pid,
+    threads: &[(Pid, libc::user_regs_struct)],
+    read_memory: &dyn Fn(usize, usize) -> Result<Vec<u8>, nix::Error>,
+) -> io::Result<()> {
+    let maps = memory_map::read_maps(pid)?;
+    let ids = process_ids(pid)?;
+
+    let mut notes = Vec::new();
+    push_note(&mut notes, NT_PRPSINFO, &encode_prpsinfo(pid, ids)?);
+    for (tid, regs) in threads {
+        push_note(&mut notes, NT_PRSTATUS, &encode_prstatus(*tid, ids, regs));
+    }
+    push_note(
+        &mut notes,
+        NT_AUXV,
+        &fs::read(format!("/proc/{}/auxv", pid))?,
+    );
+    push_note(&mut notes, NT_FILE, &encode_file_note(&maps));
+
+    let segments: Vec<&Mapping> = maps.iter().filter(|mapping| mapping.readable).collect();
+    let phnum = 1 + segments.len();
+    let notes_offset = ELF_HEADER_SIZE + phnum * PROGRAM_HEADER_SIZE;
+    // Memory contents start on a page boundary, as in the kernel's dumps
+    let data_offset = align_up(notes_offset + notes.len(), PAGE_SIZE);
+
+    let mut out = BufWriter::new(fs::File::create(path)?);
+    out.write_all(&encode_elf_header(phnum))?;
+    out.write_all(&encode_program_header(
+        PT_NOTE,
+        0,
+        notes_offset,
+        0,
+        notes.len(),
+        0,
+        4,
+    ))?;
+    // The PT_LOAD headers are filled in once we know how much of each mapping could be read
+    out.write_all(&vec![0; segments.len() * PROGRAM_HEADER_SIZE])?;
+    out.write_all(&notes)?;
+    out.write_all(&vec![0; data_offset - notes_offset - notes.len()])?;
+
+    let mut headers = Vec::with_capacity(segments.len() * PROGRAM_HEADER_SIZE);
+    let mut offset = data_offset;
+    for mapping in segments.iter() {
+        let size = mapping.end - mapping.start;
+        // Some readable mappings, like [vvar], can't actually be read; we save what we could
+        // read before the first failure
+        let mut saved = 0;
+        while saved < size {
+            match read_memory(mapping.start + saved, COPY_CHUNK_SIZE.min(size - saved)) {
+                Ok(data) if !data.is_empty() => {
+                    out.write_all(&data)?;
+                    saved += data.len();
+                }
+                _ => break,
+            }
+        }
+        let flags = if mapping.readable { PF_R } else { 0 }
+            | if mapping.writable { PF_W } else { 0 }
+            | if mapping.executable { PF_X } else { 0 };
+        headers.extend(encode_program_header(
+            PT_LOAD,
+            flags,
+            offset,
+            mapping.start,
+            saved,
+            size,
+            PAGE_SIZE,
+        ));
+        offset += saved;
+    }
+    out.seek(SeekFrom::Start(
+        (ELF_HEADER_SIZE + PROGRAM_HEADER_SIZE) as u64,
+    ))?;
+    out.write_all(&headers)?;
+    out.flush()
+}
+
+fn encode_elf_header(phnum: usize) -> Vec<u8> {
+    let mut header = vec![0; ELF_HEADER_SIZE];
+    // Magic, 64-bit, little-endian, ELF version 1, System V ABI
+    header[..7].copy_from_slice(b"\x7fELF\x02\x01\x01");
+    header[16..18].copy_from_slice(&ET_CORE.to_le_bytes());
+    header[18..20].copy_from_slice(&EM_X86_64.to_le_bytes());
+    header[20..24].copy_from_slice(&1u32.to_le_bytes());
+    header[32..40].copy_from_slice(&(ELF_HEADER_SIZE as u64).to_le_bytes());
+    header[52..54].copy_from_slice(&(ELF_HEADER_SIZE as u16).to_le_bytes());
+    header[54..56].copy_from_slice(&(PROGRAM_HEADER_SIZE as u16).to_le_bytes());
+    header[56..58].copy_from_slice(&(phnum as u16).to_le_bytes());
+    header
+}
+
+fn encode_program_header(
+    p_type: u32,
+    flags: u32,
+    offset: usize,
+    vaddr: usize,
+    filesz: usize,
+    memsz: usize,
+    align: usize,
+) -> Vec<u8> {
+    let mut header = Vec::with_capacity(PROGRAM_HEADER_SIZE);
+    header.extend_from_slice(&p_type.to_le_bytes());
+    header.extend_from_slice(&flags.to_le_bytes());
+    // p_paddr, between p_vaddr and p_filesz, is left 0
+    for value in [offset, vaddr, 0, filesz, memsz, align].iter() {
+        header.extend_from_slice(&(*value as u64).to_le_bytes());
+    }
+    header
+}
+
+/// Appends a note owned by "CORE", with its name and descriptor padded to 4 bytes.
+fn push_note(notes: &mut Vec<u8>, note_type: u32, desc: &[u8]) {
+    let name = b"CORE\0";
+    notes.extend_from_slice(&(name.len() as u32).to_le_bytes());
+    notes.extend_from_slice(&(desc.len() as u32).to_le_bytes());
+    notes.extend_from_slice(&note_type.to_le_bytes());
+    notes.extend_from_slice(name);
+    notes.resize(align_up(notes.len(), 4), 0);
+    notes.extend_from_slice(desc);
+    notes.resize(align_up(notes.len(), 4), 0);
+}
+
+/// Parent, process group and session IDs of a process.
+type ProcessIds = (i32, i32, i32);
+
+fn process_ids(pid: Pid) -> io::Result<ProcessIds> {
+    let stat = fs::read_to_string(format!("/proc/{}/stat", pid))?;
+    // The command name in parentheses may contain spaces, so start after it
+    let fields: Vec<&str> = stat[stat.rfind(')').unwrap_or(0) + 1..]
+        .split_whitespace()
+        .collect();
+    let field = |index: usize| fields.get(index).and_then(|field| field.parse().ok());
+    match (field(1), field(2), field(3)) {
+        (Some(ppid), Some(pgrp), Some(sid)) => Ok((ppid, pgrp, sid)),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "malformed /proc/<pid>/stat",
+        )),
+    }
+}
+
+fn encode_prstatus(tid: Pid, ids: ProcessIds, regs: &libc::user_regs_struct) -> Vec<u8> {
+    let mut prstatus = vec![0; PRSTATUS_SIZE];
+    let (ppid, pgrp, sid) = ids;
+    for (index, id) in [tid.as_raw(), ppid, pgrp, sid].iter().enumerate() {
+        let offset = PRSTATUS_PID + 4 * index;
+        prstatus[offset..offset + 4].copy_from_slice(&id.to_le_bytes());
+    }
+    let regs = encode_registers(regs);
+    prstatus[PRSTATUS_REGS..PRSTATUS_REGS + regs.len()].copy_from_slice(&regs);
+    prstatus
+}
+
+fn encode_prpsinfo(pid: Pid, ids: ProcessIds) -> io::Result<Vec<u8>> {
+    let mut prpsinfo = vec![0; PRPSINFO_SIZE];
+    // The process is stopped under us
+    prpsinfo[PRPSINFO_SNAME] = b't';
+    let metadata = fs::metadata(format!("/proc/{}", pid))?;
+    prpsinfo[PRPSINFO_UID..PRPSINFO_UID + 4].copy_from_slice(&metadata.uid().to_le_bytes());
+    prpsinfo[PRPSINFO_UID + 4..PRPSINFO_UID + 8].copy_from_slice(&metadata.gid().to_le_bytes());
+    let (ppid, pgrp, sid) = ids;
+    for (index, id) in [pid.as_raw(), ppid, pgrp, sid].iter().enumerate() {
+        let offset = PRPSINFO_PID + 4 * index;
+        prpsinfo[offset..offset + 4].copy_from_slice(&id.to_le_bytes());
+    }
+    // Both strings are truncated to leave room for a terminating NUL
+    let comm = fs::read(format!("/proc/{}/comm", pid))?;
+    let comm = comm.strip_suffix(b"\n").unwrap_or(&comm[..]);
+    let len = comm.len().min(FNAME_LEN - 1);
+    prpsinfo[PRPSINFO_FNAME..PRPSINFO_FNAME + len].copy_from_slice(&comm[..len]);
+    let cmdline = fs::read(format!("/proc/{}/cmdline", pid))?;
+    let args: Vec<u8> = cmdline
+        .strip_suffix(b"\0")
+        .unwrap_or(&cmdline[..])
+        .iter()
+        .map(|byte| if *byte == 0 { b' ' } else { *byte })
+        .collect();
+    let len = args.len().min(PSARGS_LEN - 1);
+    prpsinfo[PRPSINFO_PSARGS..PRPSINFO_PSARGS + len].copy_from_slice(&args[..len]);
+    Ok(prpsinfo)
+}
+
+/// Encodes the file-backed mappings as an NT_FILE note, the reverse of `parse_file_note`.
+fn encode_file_note(maps: &[Mapping]) -> Vec<u8> {
+    let files: Vec<&Mapping> = maps.iter().filter(|mapping| mapping.is_file()).collect();
+    let mut note = Vec::new();
+    note.extend_from_slice(&(files.len() as u64).to_le_bytes());
+    note.extend_from_slice(&(PAGE_SIZE as u64).to_le_bytes());
+    for mapping in files.iter() {
+        for value in [mapping.start, mapping.end, mapping.offset / PAGE_SIZE].iter() {
+            note.extend_from_slice(&(*value as u64).to_le_bytes());
+        }
+    }
+    for mapping in files.iter() {
+        note.extend_from_slice(mapping.path.as_ref().unwrap().as_bytes());
+        note.push(0);
+    }
+    note
+}
+
+fn align_up(value: usize, align: usize) -> usize {
+    (value + align - 1) / align * align
+}
+
+/// Parses the registers saved in an NT_PRSTATUS note, which are laid out as in
+/// `user_regs_struct`.
+fn parse_registers(data: &[u8]) -> Option<libc::user_regs_struct> {
+    let reg = |index: usize| read_u64(data, index * 8);
+    Some(libc::user_regs_struct {
+        r15: reg(0)?,
+        r14: reg(1)?,
+        r13: reg(2)?,
+        r12: reg(3)?,
+        rbp: reg(4)?,
+        rbx: reg(5)?,
+        r11: reg(6)?,
+        r10: reg(7)?,
+        r9: reg(8)?,
+        r8: reg(9)?,
+        rax: reg(10)?,
+        rcx: reg(11)?,
+        rdx: reg(12)?,
+        rsi: reg(13)?,
+        rdi: reg(14)?,
+        orig_rax: reg(15)?,
+        rip: reg(16)?,
+        cs: reg(17)?,
+        eflags: reg(18)?,
+        rsp: reg(19)?,
+        ss: reg(20)?,
+        fs_base: reg(21)?,
+        gs_base: reg(22)?,
+        ds: reg(23)?,
+        es: reg(24)?,
+        fs: reg(25)?,
+        gs: reg(26)?,
+    })
+}
+
+/// Lays out registers as in an NT_PRSTATUS note, the reverse of `parse_registers`.
+fn encode_registers(regs: &libc::user_regs_struct) -> Vec<u8> {
+    let values = [
+        regs.r15,
+        regs.r14,
+        regs.r13,
+        regs.r12,
+        regs.rbp,
+        regs.rbx,
+        regs.r11,
+        regs.r10,
+        regs.r9,
+        regs.r8,
+        regs.rax,
+        regs.rcx,
+        regs.rdx,
+        regs.rsi,
+        regs.rdi,
+        regs.orig_rax,
+        regs.rip,
+        regs.cs,
+        regs.eflags,
+        regs.rsp,
+        regs.ss,
+        regs.fs_base,
+        regs.gs_base,
+        regs.ds,
+        regs.es,
+        regs.fs,
+        regs.gs,
+    ];
+    values
+        .iter()
+        .flat_map(|value| value.to_le_bytes().to_vec())
+        .collect()
+}
+
+/// Parses an NT_FILE note: a count and page size, then the start, end and page offset of each
+/// mapping, then their file names.
+fn parse_file_note(data: &[u8]) -> Option<Vec<MappedFile>> {
+    let count = read_u64(data, 0)? as usize;
+    let page_size = read_u64(data, 8)? as usize;
+    let names_start = count.checked_mul(24)?.checked_add(16)?;
+    let mut names = data.get(names_start..)?.split(|byte| *byte == 0);
+    (0..count)
+        .map(|index| {
+            // Entries come before names_start, so their offsets can't overflow
+            let entry = 16 + index * 24;
+            Some(MappedFile {
+                start: read_u64(data, entry)? as usize,
+                end: read_u64(data, entry + 8)? as usize,
+                offset: (read_u64(data, entry + 16)? as usize).checked_mul(page_size)?,
+                path: String::from_utf8_lossy(names.next()?).into_owned(),
+            })
+        })
+        .collect()
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(
+        data.get(offset..offset.checked_add(2)?)?
+            .try_into()
+            .unwrap(),
+    ))
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(
+        data.get(offset..offset.checked_add(4)?)?
+            .try_into()
+            .unwrap(),
+    ))
+}
+
+fn read_u64(data: &[u8], offset: usize) -> Option<u64> {
+    Some(u64::from_le_bytes(
+        data.get(offset..offset.checked_add(8)?)?
+            .try_into()
+            .unwrap(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static MARKER: [u8; 16] = *b"deet core test\0\0";
+
+    fn distinct_registers() -> (Vec<u8>, libc::user_regs_struct) {
+        let bytes: Vec<u8> = (0..27u64)
+            .flat_map(|index| (0x1000 + index).to_le_bytes().to_vec())
+            .collect();
+        let regs = parse_registers(&bytes).unwrap();
+        (bytes, regs)
+    }
+
+    #[test]
+    fn registers_round_trip() {
+        let (bytes, regs) = distinct_registers();
+        assert_eq!(regs.r15, 0x1000);
+        assert_eq!(regs.rip, 0x1010);
+        assert_eq!(regs.rsp, 0x1013);
+        assert_eq!(regs.gs, 0x101a);
+        assert_eq!(encode_registers(&regs), bytes);
+    }
+
+    #[test]
+    fn parse_registers_rejects_short_data() {
+        let (bytes, _) = distinct_registers();
+        assert!(parse_registers(&bytes[..bytes.len() - 1]).is_none());
+    }
+
+    #[test]
+    fn file_note_round_trip() {
+        let mapping = |start: usize, offset: usize, path: Option<&str>| Mapping {
+            start,
+            end: start + 2 * PAGE_SIZE,
+            readable: true,
+            writable: false,
+            executable: false,
+            offset,
+            path: path.map(|path| path.to_string()),
+        };
+        let maps = vec![
+            mapping(0x400000, 0, Some("/bin/true")),
+            mapping(0x600000, 0, None),
+            mapping(0x7ff000, 0, Some("[stack]")),
+            mapping(0x800000, 3 * PAGE_SIZE, Some("/lib/libc.so.6")),
+        ];
+        let files = parse_file_note(&encode_file_note(&maps)).unwrap();
+        let files: Vec<(usize, usize, usize, &str)> = files
+            .iter()
+            .map(|file| (file.start, file.end, file.offset, &file.path[..]))
+            .collect();
+        assert_eq!(
+            files,
+            vec![
+                (0x400000, 0x402000, 0, "/bin/true"),
+                (0x800000, 0x802000, 3 * PAGE_SIZE, "/lib/libc.so.6"),
+            ]
+        );
+    }
+
+    #[test]
+    fn open_reads_written_core() {
+        let pid = Pid::this();
+        let (_, mut regs) = distinct_registers();
+        regs.rip = MARKER.as_ptr() as u64;
+        let marker = MARKER.as_ptr() as usize;
+        // Save only the mapping holding the marker, rather than the whole test process
+        let maps = memory_map::read_maps(pid).unwrap();
+        let saved = maps
+            .iter()
+            .find(|mapping| mapping.contains(marker))
+            .unwrap();
+        let read_memory = |addr: usize, len: usize| -> Result<Vec<u8>, nix::Error> {
+            if addr < saved.start || addr + len > saved.end {
+                return Err(nix::Error::Sys(Errno::EIO));
+            }
+            let mut bytes = vec![0; len];
+            fs::File::open("/proc/self/mem")
+                .and_then(|mem| mem.read_exact_at(&mut bytes, addr as u64))
+                .map_err(|_| nix::Error::Sys(Errno::EIO))?;
+            Ok(bytes)
+        };
+        let path = std::env::temp_dir().join(format!("deet-core-test-{}", pid));
+        let path = path.to_str().unwrap();
+        write(path, pid, &[(pid, regs)], &read_memory).unwrap();
+        let core = Core::open(path);
+        fs::remove_file(path).unwrap();
+        let core = core.unwrap();
+
+        assert_eq!(core.current_thread_id(), pid.as_raw());
+        assert_eq!(core.registers().unwrap().rip, marker as u64);
+        assert_eq!(core.read_memory(marker, MARKER.len()).unwrap(), MARKER);
+        assert_eq!(core.auxv(libc::AT_PAGESZ), Some(PAGE_SIZE));
+        let exe = std::env::current_exe().unwrap();
+        assert!(core
+            .mappings()
+            .iter()
+            .any(|mapping| mapping.path.as_deref() == exe.to_str()));
+    }
+}
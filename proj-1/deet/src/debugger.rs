@@ -1,13 +1,26 @@
-use std::{
-    collections::HashMap,
-    fs,
-    io::{BufRead, BufReader},
-};
+use std::fs;
+use std::path::Path;
 
+use crate::breakpoint::{BreakpointTable, Condition};
+use crate::coredump::{self, Core};
 use crate::debugger_command::DebuggerCommand;
-use crate::dwarf_data::{DwarfData, Error as DwarfError};
-use crate::inferior::{Inferior, Status};
+use crate::disassemble;
+use crate::dwarf_data::{DwarfData, Error as DwarfError, Function, Line, Location, Type, Variable};
+use crate::dwarf_expr::{self, EvalContext, EvalError, Piece, PieceLocation};
+use crate::examine;
+use crate::expr;
+use crate::inferior::{ForkPolicy, Inferior, Status};
+use crate::memory_map;
+use crate::registers;
+use crate::shlib::{Rendezvous, SharedLibrary};
+use crate::signals::{self, SignalTable};
+use crate::source::SourceCache;
+use crate::target::Target;
+use crate::unwind::Unwinder;
+use crate::watchpoint::{self, Watchpoint, WatchpointTable};
+use nix::errno::Errno;
 use nix::sys::signal::Signal;
+use nix::unistd::Pid;
 use rustyline::error::ReadlineError;
 use rustyline::Editor;
 pub struct Debugger {
@@ -15,8 +28,25 @@ pub struct Debugger {
     history_path: String,
     readline: Editor<()>,
     inferior: Option<Inferior>,
+    /// Core dump to examine while there is no live inferior.
+    core: Option<Core>,
     debug_data: DwarfData,
-    break_point: HashMap<usize, u8>,
+    unwinder: Unwinder,
+    breakpoints: BreakpointTable,
+    watchpoints: WatchpointTable,
+    fork_policy: ForkPolicy,
+    /// Start the inferior with address space randomization turned off, as gdb does by default.
+    disable_randomization: bool,
+    signals: SignalTable,
+    /// Format of the last `x` command, which later ones default to.
+    examine_format: examine::Format,
+    sources: SourceCache,
+    /// File and first line of what a bare `list` shows next.
+    listing: Option<(String, usize)>,
+    /// Shared libraries the inferior has loaded.
+    shared_libraries: Vec<SharedLibrary>,
+    /// None if the inferior is statically linked, or not running.
+    rendezvous: Option<Rendezvous>,
 }
 
 impl Debugger {
@@ -48,201 +78,1931 @@ impl Debugger {
             history_path,
             readline,
             inferior: None,
+            core: None,
             debug_data,
-            break_point: HashMap::new(),
+            unwinder: Unwinder::new(target),
+            breakpoints: BreakpointTable::new(),
+            watchpoints: WatchpointTable::new(),
+            fork_policy: ForkPolicy::default(),
+            disable_randomization: true,
+            signals: SignalTable::new(),
+            examine_format: examine::Format::new(),
+            sources: SourceCache::new(),
+            listing: None,
+            shared_libraries: Vec::new(),
+            rendezvous: None,
         }
     }
-    pub fn print_line_code(
-        &self,
-        file: &str,
-        num: &usize,
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        let mut cnt = *num;
-        let data = fs::File::open(file)?;
-        let reader = BufReader::new(data);
-        for line in reader.lines() {
-            cnt -= 1;
-            if cnt == 0 {
-                println!("line: {} \ncode: {}", num, line?);
-                break;
+    pub fn print_line_code(&self, file: &str, num: &usize) {
+        if let Some(code) = self.source_line(file, *num) {
+            println!("line: {} \ncode: {}", num, code);
+        }
+    }
+
+    /// Returns line `number`, counting from 1, of a source file named in the debugging
+    /// information.
+    fn source_line(&self, file: &str, number: usize) -> Option<String> {
+        self.sources
+            .line(file, self.debug_data.get_comp_dir(file), number)
+    }
+
+    /// Returns where commands that only look at the program's registers and memory read them
+    /// from: the live inferior, or else the core dump being examined.
+    fn state(&self) -> Option<&dyn Target> {
+        match (&self.inferior, &self.core) {
+            (Some(inferior), _) => Some(inferior),
+            (None, Some(core)) => Some(core),
+            (None, None) => None,
+        }
+    }
+
+    /// Loads a core dump, so that the program can be examined as it was when it dumped.
+    pub fn load_core(&mut self, path: &str) {
+        let core = match Core::open(path) {
+            Ok(core) => core,
+            Err(err) => {
+                println!("Could not load core file {}: {}", path, err);
+                return;
+            }
+        };
+        if !core.command().is_empty() {
+            println!("Core was generated by `{}'.", core.command());
+        }
+        if let Some(signal) = core.signal() {
+            println!("Program terminated with signal {}.", signal);
+        }
+        // The program may have been position-independent, like a live one
+        let bias = core
+            .auxv(memory_map::AT_ENTRY)
+            .map_or(0, |entry| entry.wrapping_sub(self.debug_data.entry_point()));
+        self.debug_data.set_load_bias(bias);
+        self.unwinder.set_load_bias(bias);
+        self.unwinder.load_modules(&core.mappings());
+        println!("[Current thread is 1 (LWP {})]", core.current_thread_id());
+        let rip = core.registers().map(|regs| regs.rip as usize);
+        self.core = Some(core);
+        if let Ok(rip) = rip {
+            self.print_stop_location(rip);
+        }
+    }
+
+    /// Returns the source line the current thread is stopped at.
+    fn current_line(&self) -> Option<Line> {
+        let regs = self.state()?.registers().ok()?;
+        self.debug_data.get_line_from_addr(regs.rip as usize)
+    }
+
+    /// Finds the file and line meant by the argument of `list`: a line in the file being
+    /// listed, a function, or file:line.
+    fn resolve_list_location(&self, location: &str) -> Result<(String, usize), String> {
+        if let Some((file, line)) = location.rsplit_once(':') {
+            let number = line
+                .parse()
+                .map_err(|_| format!("Invalid line number \"{}\"", line))?;
+            let file = resolve_file(&self.debug_data, file)?;
+            return Ok((file.to_string(), number));
+        }
+        if let Ok(number) = location.parse::<usize>() {
+            let file = match &self.listing {
+                Some((file, _)) => Some(file.clone()),
+                None => self
+                    .current_line()
+                    .map(|line| line.file)
+                    .or_else(|| self.debug_data.get_main_file().map(|file| file.to_string())),
+            };
+            return file
+                .map(|file| (file, number))
+                .ok_or_else(|| "No symbol table is loaded.".to_string());
+        }
+        self.debug_data
+            .get_function_source(location)
+            .ok_or_else(|| format!("Function \"{}\" not defined.", location))
+    }
+
+    /// Handles `list`, printing lines of source around `location`, or the lines after the ones
+    /// listed last time. The line the inferior is stopped at is marked.
+    fn list(&mut self, location: Option<&str>) {
+        let (file, first) = match location {
+            Some(location) => match self.resolve_list_location(location) {
+                Ok((file, line)) => (file, list_start(line)),
+                Err(err) => {
+                    println!("{}", err);
+                    return;
+                }
+            },
+            None => match self.listing.take() {
+                Some(listing) => listing,
+                None => match self.debug_data.get_function_source("main") {
+                    Some((file, line)) => (file, list_start(line)),
+                    None => {
+                        println!("No symbol table is loaded.");
+                        return;
+                    }
+                },
+            },
+        };
+        let lines = match self
+            .sources
+            .lines(&file, self.debug_data.get_comp_dir(&file))
+        {
+            Some(lines) => lines,
+            None => {
+                println!("Could not find source file {}.", file);
+                println!("Use \"directory <path>\" to add a directory to search for it.");
+                return;
+            }
+        };
+        if first > lines.len() {
+            println!(
+                "Line number {} out of range; \"{}\" has {} lines.",
+                first,
+                file,
+                lines.len()
+            );
+            return;
+        }
+        let current = self.current_line();
+        let last = (first + LIST_SIZE - 1).min(lines.len());
+        for number in first..=last {
+            let marker = match &current {
+                Some(line) if line.number == number && same_file(&line.file, &file) => "=>",
+                _ => "  ",
+            };
+            println!("{} {:<7}{}", marker, number, lines[number - 1]);
+        }
+        self.listing = Some((file, last + 1));
+    }
+
+    /// Handles `directory`, adding directories to search for source files in.
+    fn directory(&mut self, dirs: &[String]) {
+        // Add them in reverse, so that they end up searched in the order given
+        for dir in dirs.iter().rev() {
+            self.sources.add_directory(dir);
+        }
+        let mut searched = vec!["$cdir".to_string()];
+        searched.extend(
+            self.sources
+                .directories()
+                .iter()
+                .map(|dir| dir.display().to_string()),
+        );
+        println!("Source directories searched: {}", searched.join(":"));
+    }
+
+    /// Reports a change in the inferior's status to the user. If the inferior is no longer
+    /// running, it is dropped.
+    fn report_status(&mut self, status: Status) {
+        match status {
+            Status::Exited(exit_code) => {
+                println!("Child exited (status {}) ", exit_code);
+                self.inferior = None;
+            }
+            Status::Signaled(signal) => {
+                println!("Child exited due to signal {}", signal);
+                self.inferior = None;
+            }
+            Status::Stopped(signal, rip) => {
+                // Pick up the unwind tables of any shared libraries loaded since the last stop
+                if let Some(inferior) = &self.inferior {
+                    self.unwinder.refresh(inferior.pid());
+                }
+                println!(
+                    "Child stopped due to signal {} at address {:#x}",
+                    signal, rip
+                );
+                self.print_stop_location(rip);
+            }
+            Status::Exec(rip) => {
+                self.handle_exec();
+                println!("Child stopped after exec at address {:#x}", rip);
+                self.print_stop_location(rip);
+            }
+        }
+    }
+
+    fn print_stop_location(&mut self, rip: usize) {
+        let debug_data = self.debug_data_at(rip);
+        let file = debug_data.get_line_from_addr(rip);
+        let func = debug_data.get_function_from_addr(rip);
+        if file.is_some() && func.is_some() {
+            let file = file.unwrap();
+            println!("Stopped at {}({})", func.unwrap(), file);
+            let path = file.file;
+            let number = file.number;
+            self.print_line_code(&path, &number);
+            self.listing = Some((path, list_start(number)));
+        }
+    }
+
+    /// Switches over to the program the inferior just exec'd: loads its debugging information
+    /// and moves the breakpoints to wherever their locations are in the new program.
+    fn handle_exec(&mut self) {
+        let pid = self.inferior.as_ref().unwrap().pid();
+        let path = match fs::read_link(format!("/proc/{}/exe", pid)) {
+            Ok(path) => path.to_string_lossy().into_owned(),
+            Err(err) => {
+                println!("Could not find the program process {} exec'd: {}", pid, err);
+                return;
+            }
+        };
+        println!("process {} is executing new program: {}", pid, path);
+        match DwarfData::from_file(&path) {
+            Ok(debug_data) => self.debug_data = debug_data,
+            Err(_) => println!("Could not load debugging symbols from {}", path),
+        }
+        self.unwinder = Unwinder::new(&path);
+        self.target = path;
+
+        for id in self.load_program() {
+            println!("Breakpoint {} is not in the new program; it is pending", id);
+        }
+        let inferior = self.inferior.as_mut().unwrap();
+        // Watchpoints are on addresses in the old program
+        if self.watchpoints.iter().next().is_some() {
+            println!("Deleting watchpoints, which were set in the previous program");
+            if let Err(err) = self.watchpoints.clear(Some(inferior)) {
+                println!("Error removing watchpoints: {}", err);
+            }
+        }
+    }
+
+    /// Sets up for the program image the inferior has just started, or been found, running:
+    /// relocates the debugging information to where the executable was loaded, re-resolves and
+    /// inserts the breakpoints, and starts following shared libraries. Breakpoints in libraries
+    /// become pending until those are loaded; the IDs of those that weren't already are
+    /// returned.
+    fn load_program(&mut self) -> Vec<usize> {
+        // The live process takes over from any core dump
+        self.core = None;
+        let inferior = self.inferior.as_mut().unwrap();
+        // A position-independent executable is loaded wherever the kernel chose
+        let bias = memory_map::read_auxv(inferior.pid(), memory_map::AT_ENTRY)
+            .map_or(0, |entry| entry.wrapping_sub(self.debug_data.entry_point()));
+        self.debug_data.set_load_bias(bias);
+        self.unwinder.set_load_bias(bias);
+        self.shared_libraries.clear();
+        self.rendezvous = None;
+        let debug_data = &self.debug_data;
+        let pending = self
+            .breakpoints
+            .relocate(|location| resolve_breakpoint(debug_data, &[], location).ok());
+        inferior.insert_breakpoints(self.breakpoints.inserted_mut());
+        self.watch_shared_libraries();
+        pending
+    }
+
+    /// Starts following the inferior's shared libraries, with an internal breakpoint where the
+    /// dynamic linker reports changes to them, or where it will have started up. Libraries
+    /// already loaded, as when attaching, are picked up right away.
+    fn watch_shared_libraries(&mut self) {
+        self.shared_libraries.clear();
+        let inferior = self.inferior.as_mut().unwrap();
+        self.rendezvous =
+            Rendezvous::find(inferior.pid(), &|addr, len| inferior.read_memory(addr, len));
+        if let Some(rendezvous) = &self.rendezvous {
+            if let Err(err) = self
+                .breakpoints
+                .add_internal(rendezvous.breakpoint, inferior)
+            {
+                println!("Could not follow shared library loads: {}", err);
+            }
+        }
+        self.update_shared_libraries();
+    }
+
+    /// Handles the inferior trapping at the rendezvous breakpoint. The first time, at the entry
+    /// point, the dynamic linker has finished starting up and says where it will report changes
+    /// from now on, so the breakpoint moves there.
+    fn handle_rendezvous(&mut self) {
+        let (rendezvous, inferior) = match (&mut self.rendezvous, &mut self.inferior) {
+            (Some(rendezvous), Some(inferior)) => (rendezvous, inferior),
+            _ => return,
+        };
+        if rendezvous.starting {
+            let entry = rendezvous.breakpoint;
+            let started = rendezvous.started(&|addr, len| inferior.read_memory(addr, len));
+            let result = match (self.breakpoints.delete_internal(entry, inferior), started) {
+                (Ok(()), Ok(true)) => self
+                    .breakpoints
+                    .add_internal(rendezvous.breakpoint, inferior)
+                    .map_err(|err| err.to_string()),
+                (Ok(()), Ok(false)) => Err("the dynamic linker did not fill in r_brk".to_string()),
+                (Err(err), _) | (_, Err(err)) => Err(err.to_string()),
+            };
+            if let Err(err) = result {
+                println!("Could not follow shared library loads: {}", err);
+                self.rendezvous = None;
+            }
+        }
+        self.update_shared_libraries();
+    }
+
+    /// Catches up with the dynamic linker after it has changed the list of loaded objects:
+    /// loads the debugging information of new shared libraries and resolves pending breakpoints
+    /// in them.
+    fn update_shared_libraries(&mut self) {
+        let (rendezvous, inferior) = match (&self.rendezvous, &self.inferior) {
+            (Some(rendezvous), Some(inferior)) => (rendezvous, inferior),
+            _ => return,
+        };
+        let loaded = match rendezvous.loaded(&|addr, len| inferior.read_memory(addr, len)) {
+            Ok(Some(loaded)) => loaded,
+            // The dynamic linker is midway through a change, and will report again when done
+            Ok(None) => return,
+            Err(err) => {
+                println!("Could not read the list of shared libraries: {}", err);
+                return;
+            }
+        };
+        // Compare paths as /proc/<pid>/maps shows them, with symlinks resolved
+        let loaded: Vec<(String, usize)> = loaded
+            .into_iter()
+            .map(|(path, bias)| match fs::canonicalize(&path) {
+                Ok(canonical) => (canonical.to_string_lossy().into_owned(), bias),
+                Err(_) => (path, bias),
+            })
+            .collect();
+        self.shared_libraries.retain(|lib| {
+            loaded
+                .iter()
+                .any(|(path, bias)| *path == lib.path && *bias == lib.bias)
+        });
+        for (path, bias) in loaded {
+            // The vDSO is listed too, but has no file
+            if self.shared_libraries.iter().any(|lib| lib.path == path)
+                || !Path::new(&path).is_file()
+            {
+                continue;
+            }
+            let debug_data = DwarfData::from_file(&path).ok().map(|mut debug_data| {
+                debug_data.set_load_bias(bias);
+                debug_data
+            });
+            self.shared_libraries.push(SharedLibrary {
+                path,
+                bias,
+                debug_data,
+            });
+        }
+
+        let (debug_data, libraries) = (&self.debug_data, &self.shared_libraries);
+        let resolved = self.breakpoints.resolve_pending(
+            |location| resolve_breakpoint(debug_data, libraries, location).ok(),
+            self.inferior.as_mut().unwrap(),
+        );
+        match resolved {
+            Ok(resolved) => {
+                for id in resolved {
+                    println!(
+                        "Breakpoint {} resolved at {}",
+                        id,
+                        self.breakpoints.get(id).unwrap()
+                    );
+                }
+            }
+            Err(err) => println!("Could not insert breakpoints: {}", err),
+        }
+    }
+
+    /// Returns the debugging information describing the code at `addr`, from the executable or
+    /// a shared library.
+    fn debug_data_at(&self, addr: usize) -> &DwarfData {
+        // Spare reading the memory map when there is only the executable to choose from
+        if self
+            .shared_libraries
+            .iter()
+            .all(|lib| lib.debug_data.is_none())
+        {
+            return &self.debug_data;
+        }
+        let path = self
+            .inferior
+            .as_ref()
+            .and_then(|inferior| memory_map::read_maps(inferior.pid()).ok())
+            .and_then(|maps| {
+                maps.into_iter()
+                    .find(|mapping| mapping.contains(addr))?
+                    .path
+            });
+        let library =
+            path.and_then(|path| self.shared_libraries.iter().find(|lib| lib.path == path));
+        match library.and_then(|lib| lib.debug_data.as_ref()) {
+            Some(debug_data) => debug_data,
+            None => &self.debug_data,
+        }
+    }
+
+    fn print_shared_libraries(&self) {
+        if self.shared_libraries.is_empty() {
+            println!("No shared libraries loaded at this time.");
+            return;
+        }
+        println!(
+            "{:<18}  {:<9}  Shared Object Library",
+            "Load bias", "Syms Read"
+        );
+        for lib in self.shared_libraries.iter() {
+            let symbols = if lib.debug_data.is_some() {
+                "Yes"
+            } else {
+                "No"
+            };
+            println!("{:<#18x}  {:<9}  {}", lib.bias, symbols, lib.path);
+        }
+    }
+
+    /// Reads the inferior's memory as the program sees it, with the original bytes in place of
+    /// breakpoint traps.
+    fn read_program_memory(&self, addr: usize, len: usize) -> Result<Vec<u8>, nix::Error> {
+        let mut bytes = self.state().unwrap().read_memory(addr, len)?;
+        // A core dump has none of our traps in it
+        if self.inferior.is_none() {
+            return Ok(bytes);
+        }
+        for (trap_addr, orig_byte) in self.breakpoints.inserted() {
+            if addr <= *trap_addr && *trap_addr < addr + len {
+                bytes[trap_addr - addr] = *orig_byte;
+            }
+        }
+        Ok(bytes)
+    }
+
+    /// Writes to the inferior's memory, leaving breakpoint traps in place: bytes written over a
+    /// trap become the original byte restored when the breakpoint is removed.
+    fn write_program_memory(&mut self, addr: usize, bytes: &[u8]) -> Result<(), nix::Error> {
+        let mut bytes = bytes.to_vec();
+        for (trap_addr, orig_byte) in self.breakpoints.inserted_mut().iter_mut() {
+            if addr <= *trap_addr && *trap_addr < addr + bytes.len() {
+                *orig_byte = bytes[trap_addr - addr];
+                bytes[trap_addr - addr] = 0xcc;
+            }
+        }
+        self.inferior.as_mut().unwrap().write_memory(addr, &bytes)
+    }
+
+    /// Reads the NUL-terminated string at `addr`, up to a limit. The terminator isn't included.
+    fn read_c_string(&self, addr: usize) -> Result<Vec<u8>, nix::Error> {
+        const MAX_LEN: usize = 200;
+        let mut string = Vec::new();
+        while string.len() < MAX_LEN {
+            // Don't read past the end of the page, which may be the end of the mapping
+            let curr_addr = addr + string.len();
+            let len = 64usize.min(PAGE_SIZE - curr_addr % PAGE_SIZE);
+            let chunk = self.read_program_memory(curr_addr, len)?;
+            match chunk.iter().position(|byte| *byte == 0) {
+                Some(end) => {
+                    string.extend_from_slice(&chunk[..end]);
+                    break;
+                }
+                None => string.extend_from_slice(&chunk),
+            }
+        }
+        Ok(string)
+    }
+
+    /// Handles `x/NFU <location>`, printing memory at an address or at a variable.
+    fn examine(&mut self, spec: &str, location: &str) {
+        let format = match examine::Format::parse(spec, self.examine_format) {
+            Ok(format) => format,
+            Err(err) => {
+                println!("{}", err);
+                return;
+            }
+        };
+        self.examine_format = format;
+        if self.state().is_none() {
+            println!("The program is not being run.");
+            return;
+        }
+        let location = location.trim_start_matches('&');
+        let addr = if location.is_empty() {
+            println!("Argument required (starting display address).");
+            return;
+        } else if let Some(addr) = location.strip_prefix("0x") {
+            match usize::from_str_radix(addr, 16) {
+                Ok(addr) => addr,
+                Err(_) => {
+                    println!("Invalid address");
+                    return;
+                }
+            }
+        } else if let Ok(addr) = location.parse::<usize>() {
+            addr
+        } else {
+            match self.locate_variable(location) {
+                Ok((addr, _)) => addr,
+                Err(err) => {
+                    println!("{}", err);
+                    return;
+                }
+            }
+        };
+        let result = match format.letter {
+            's' => self.examine_strings(addr, format.count),
+            'i' => self.examine_instructions(addr, format.count),
+            _ => self.examine_units(addr, format),
+        };
+        if result.is_err() {
+            println!("Cannot access memory at address {:#x}", addr);
+        }
+    }
+
+    fn examine_units(&self, addr: usize, format: examine::Format) -> Result<(), nix::Error> {
+        let len = format
+            .count
+            .checked_mul(format.unit)
+            .filter(|len| addr.checked_add(*len).is_some())
+            .ok_or(nix::Error::Sys(Errno::EFAULT))?;
+        let line_len = format.unit * format.per_line();
+        // Read a page's worth of whole lines at a time, so a large count doesn't have to fit in
+        // our memory all at once
+        let chunk_len = PAGE_SIZE / line_len * line_len;
+        for chunk_start in (0..len).step_by(chunk_len) {
+            let chunk_addr = addr + chunk_start;
+            let bytes = self.read_program_memory(chunk_addr, chunk_len.min(len - chunk_start))?;
+            for (i, line) in bytes.chunks(line_len).enumerate() {
+                let units: Vec<String> = line
+                    .chunks(format.unit)
+                    .map(|unit| format.format_unit(unit))
+                    .collect();
+                println!("{:#x}:\t{}", chunk_addr + i * line_len, units.join("\t"));
+            }
+        }
+        Ok(())
+    }
+
+    fn examine_instructions(&self, mut addr: usize, count: usize) -> Result<(), nix::Error> {
+        let rip = self.state().unwrap().registers()?.rip as usize;
+        let mut remaining = count;
+        while remaining > 0 {
+            // Read at most a page at a time, falling back to the rest of the page if the full
+            // length would run off the mapping
+            let len = remaining
+                .saturating_mul(disassemble::MAX_INSTRUCTION_LEN)
+                .min(PAGE_SIZE);
+            let bytes = self
+                .read_program_memory(addr, len)
+                .or_else(|_| self.read_program_memory(addr, PAGE_SIZE - addr % PAGE_SIZE))?;
+            let instructions = disassemble::decode(&bytes, addr, Some(remaining));
+            let debug_data = self.debug_data_at(addr);
+            for instruction in instructions.iter() {
+                let symbol = match debug_data.get_function_containing(instruction.addr) {
+                    Some(func) => {
+                        format!(" <{}+{}>", func.name, instruction.addr - func.address)
+                    }
+                    None => String::new(),
+                };
+                println!(
+                    "{} {:#x}{}:\t{}",
+                    if instruction.addr == rip { "=>" } else { "  " },
+                    instruction.addr,
+                    symbol,
+                    instruction.text
+                );
+            }
+            // Nothing decoded means the next instruction runs off the end of the mapping
+            match instructions.last() {
+                Some(last) => {
+                    remaining -= instructions.len();
+                    addr = last
+                        .addr
+                        .checked_add(last.len)
+                        .ok_or(nix::Error::Sys(Errno::EFAULT))?;
+                }
+                None => break,
+            }
+        }
+        Ok(())
+    }
+
+    /// Executes one machine instruction, or with `over_calls`, a whole call, and shows where
+    /// the inferior ended up.
+    fn step_instruction(&mut self, over_calls: bool) {
+        if self.inferior.is_none() {
+            println!("No inferior to step");
+            return;
+        }
+        let result = if over_calls {
+            self.next_instruction()
+        } else {
+            self.single_step()
+        };
+        let result = result.and_then(|stopped| stopped.map_or_else(|| self.step_done(), Ok));
+        match result {
+            Ok(Status::Stopped(Signal::SIGTRAP, rip)) => {
+                let debug_data = self.debug_data_at(rip);
+                let func = debug_data.get_function_from_addr(rip);
+                let func = func.as_deref().unwrap_or("??");
+                match debug_data.get_line_from_addr(rip) {
+                    Some(line) => {
+                        println!("{:#x} in {} at {}", rip, func, line);
+                        if let Some(source) = self.source_line(&line.file, line.number) {
+                            println!("{}\t{}", line.number, source);
+                        }
+                    }
+                    None => println!("{:#x} in {} ()", rip, func),
+                }
+            }
+            Ok(status) => self.report_status(status),
+            Err(err) => println!("Error stepping inferior: {}", err),
+        }
+    }
+
+    /// Disassembles the function called `name`, or the one the inferior is stopped in, marking
+    /// the current instruction and showing the source line each run of instructions is from.
+    fn disassemble(&self, name: Option<&str>) {
+        let state = match self.state() {
+            Some(state) => state,
+            None => {
+                println!("The program is not being run.");
+                return;
+            }
+        };
+        let rip = match state.registers() {
+            Ok(regs) => regs.rip as usize,
+            Err(err) => {
+                println!("Could not read registers: {}", err);
+                return;
+            }
+        };
+        let found = match name {
+            // The executable's functions come first, as for breakpoints
+            Some(name) => std::iter::once(&self.debug_data)
+                .chain(
+                    self.shared_libraries
+                        .iter()
+                        .filter_map(|lib| lib.debug_data.as_ref()),
+                )
+                .find_map(|debug_data| {
+                    let addr = debug_data.get_addr_for_function(None, name)?;
+                    Some((debug_data, debug_data.get_function_containing(addr)?))
+                }),
+            None => {
+                let debug_data = self.debug_data_at(rip);
+                debug_data
+                    .get_function_containing(rip)
+                    .map(|func| (debug_data, func))
+            }
+        };
+        let (debug_data, func) = match (found, name) {
+            (Some(found), _) => found,
+            (None, Some(name)) => {
+                println!("No symbol \"{}\" in current context.", name);
+                return;
+            }
+            (None, None) => {
+                println!("No function contains program counter for selected frame.");
+                return;
+            }
+        };
+        let bytes = match self.read_program_memory(func.address, func.text_length) {
+            Ok(bytes) => bytes,
+            Err(_) => {
+                println!("Cannot access memory at address {:#x}", func.address);
+                return;
+            }
+        };
+        println!("Dump of assembler code for function {}:", func.name);
+        let mut last_line = None;
+        for instruction in disassemble::decode(&bytes, func.address, None) {
+            if let Some(line) = debug_data.get_line_from_addr(instruction.addr) {
+                if last_line != Some((line.file.clone(), line.number)) {
+                    let source = self
+                        .source_line(&line.file, line.number)
+                        .unwrap_or_default();
+                    println!("{}\t{}", line, source.trim());
+                    last_line = Some((line.file, line.number));
+                }
+            }
+            println!(
+                "{} {:#018x} <+{}>:\t{}",
+                if instruction.addr == rip { "=>" } else { "  " },
+                instruction.addr,
+                instruction.addr - func.address,
+                instruction.text
+            );
+        }
+        println!("End of assembler dump.");
+    }
+
+    fn examine_strings(&self, mut addr: usize, count: usize) -> Result<(), nix::Error> {
+        for _ in 0..count {
+            let string = self.read_c_string(addr)?;
+            println!("{:#x}:\t{:?}", addr, String::from_utf8_lossy(&string));
+            addr += string.len() + 1;
+        }
+        Ok(())
+    }
+
+    /// Prints the `handle` settings of `signal`, or of every signal.
+    fn print_signals(&self, signal: Option<Signal>) {
+        let yes_no = |flag: bool| if flag { "Yes" } else { "No" };
+        println!("Signal      Stop  Print  Pass to program");
+        for (s, policy) in self.signals.iter() {
+            if signal.is_none() || signal == Some(*s) {
+                println!(
+                    "{:<11} {:<5} {:<6} {}",
+                    s,
+                    yes_no(policy.stop),
+                    yes_no(policy.print),
+                    yes_no(policy.pass)
+                );
+            }
+        }
+    }
+
+    /// Prints the named registers of the current thread, or all of them.
+    fn print_registers(&self, names: &[String]) {
+        let state = match self.state() {
+            Some(state) => state,
+            None => {
+                println!("The program has no registers now.");
+                return;
+            }
+        };
+        let regs = match state.registers() {
+            Ok(regs) => regs,
+            Err(err) => {
+                println!("Could not read registers: {}", err);
+                return;
+            }
+        };
+        let names: Vec<&str> = if names.is_empty() {
+            registers::NAMES.to_vec()
+        } else {
+            names
+                .iter()
+                .map(|name| name.trim_start_matches('$'))
+                .collect()
+        };
+        for name in names {
+            let value = match registers::register(&regs, name) {
+                Some(value) => value,
+                None => {
+                    println!("Invalid register `{}'", name);
+                    return;
+                }
+            };
+            let natural = match name {
+                "rip" | "pc" => match self
+                    .debug_data_at(value as usize)
+                    .get_function_from_addr(value as usize)
+                {
+                    Some(func) => format!("{:#x} <{}>", value, func),
+                    None => format!("{:#x}", value),
+                },
+                "rsp" | "rbp" => format!("{:#x}", value),
+                "rflags" | "eflags" => registers::format_rflags(value),
+                _ => format!("{}", value as i64),
+            };
+            println!("{:<15}{:<19}{}", name, format!("{:#x}", value), natural);
+        }
+    }
+
+    /// Sets a register of the current thread from `assignment`, e.g. "rax = 42".
+    fn set_register(&mut self, assignment: &str) {
+        let (name, value) = match split_assignment(assignment) {
+            Some(assignment) => assignment,
+            None => {
+                println!("Usage: set $<register> = <expression>");
+                return;
+            }
+        };
+        let mut regs = match &self.inferior {
+            Some(inferior) => match inferior.registers() {
+                Ok(regs) => regs,
+                Err(err) => {
+                    println!("Could not read registers: {}", err);
+                    return;
+                }
+            },
+            None => {
+                println!("The program has no registers now.");
+                return;
+            }
+        };
+        let register = match registers::register_mut(&mut regs, name) {
+            Some(register) => register,
+            None => {
+                println!("Invalid register `{}'", name);
+                return;
+            }
+        };
+        match expr::parse(value)
+            .and_then(|expr| expr.evaluate(&|name: &str| self.variable_as_i64(name)))
+        {
+            Ok(value) => *register = value as u64,
+            Err(err) => {
+                println!("{}", err);
+                return;
+            }
+        }
+        if let Err(err) = self.inferior.as_mut().unwrap().set_registers(regs) {
+            println!("Could not set register {}: {}", name, err);
+        }
+    }
+
+    /// Sets a variable in the innermost frame from `assignment`, e.g. "count = count + 1". Values
+    /// are integer expressions, except that a float or double may also be set to a number such
+    /// as 2.5.
+    fn set_variable(&mut self, assignment: &str) {
+        let (name, value) = match split_assignment(assignment) {
+            Some(assignment) => assignment,
+            None => {
+                println!("Usage: set var <variable> = <expression>");
+                return;
+            }
+        };
+        if self.inferior.is_none() {
+            println!("The program is not being run.");
+            return;
+        }
+        match self.assign_variable(name, value) {
+            Ok(()) => {
+                // The watched values may have changed under the watchpoints
+                if let Err(err) = self.watchpoints.install(self.inferior.as_mut().unwrap()) {
+                    println!("Error re-reading watchpoints: {}", err);
+                }
+            }
+            Err(err) => println!("{}", err),
+        }
+    }
+
+    fn assign_variable(&mut self, name: &str, value: &str) -> Result<(), String> {
+        let inferior = self.inferior.as_ref().unwrap();
+        let regs = inferior.registers().map_err(|err| err.to_string())?;
+        let (var, func) = self
+            .find_variable(regs.rip as usize, name)
+            .ok_or_else(|| format!("No symbol \"{}\" in current context.", name))?;
+        // Expressions work in integers, so a float or double can only take a fraction as a
+        // plain number
+        let float = value
+            .trim()
+            .parse()
+            .ok()
+            .and_then(|value| self.debug_data.encode_float(&var.entity_type, value));
+        let bytes = match float {
+            Some(bytes) => bytes,
+            None => {
+                let value = expr::parse(value)
+                    .and_then(|expr| expr.evaluate(&|name: &str| self.variable_as_i64(name)))
+                    .map_err(|err| {
+                        if self.debug_data.contains_floating_point(&var.entity_type) {
+                            format!(
+                                "{} (only integer expressions or a number can be assigned)",
+                                err
+                            )
+                        } else {
+                            err
+                        }
+                    })?;
+                self.debug_data
+                    .encode_value(&var.entity_type, value)
+                    .ok_or_else(|| {
+                        format!("Cannot assign to \"{}\": it is not a scalar value", name)
+                    })?
             }
+        };
+        let pieces = self
+            .with_variable_pieces(var, func, |pieces, _| Ok(pieces.to_vec()))
+            .map_err(|err| format!("Could not locate {}: {}", name, err))?;
+        self.write_pieces(&pieces, &bytes)
+    }
+
+    /// Writes an object back to where the pieces of its DWARF location say it lives.
+    fn write_pieces(&mut self, pieces: &[Piece], bytes: &[u8]) -> Result<(), String> {
+        let mut offset = 0;
+        for piece in pieces {
+            let len = piece.size.unwrap_or(bytes.len() - offset.min(bytes.len()));
+            let chunk = &bytes[offset.min(bytes.len())..(offset + len).min(bytes.len())];
+            match &piece.location {
+                PieceLocation::Memory(addr) => self
+                    .write_program_memory(*addr, chunk)
+                    .map_err(|_| format!("Cannot access memory at address {:#x}", addr))?,
+                PieceLocation::Register(register) => {
+                    let inferior = self.inferior.as_mut().unwrap();
+                    let mut regs = inferior.registers().map_err(|err| err.to_string())?;
+                    let mut value = dwarf_expr::dwarf_register(&regs, *register)
+                        .ok_or_else(|| EvalError::MissingRegister(*register).to_string())?
+                        .to_le_bytes();
+                    let len = chunk.len().min(value.len());
+                    value[..len].copy_from_slice(&chunk[..len]);
+                    dwarf_expr::set_dwarf_register(&mut regs, *register, u64::from_le_bytes(value));
+                    inferior
+                        .set_registers(regs)
+                        .map_err(|err| err.to_string())?;
+                }
+                PieceLocation::Empty => {
+                    return Err("Cannot assign to a value that has been optimized out.".to_string())
+                }
+                PieceLocation::Value(_) | PieceLocation::Bytes(_) => {
+                    return Err("Left operand of assignment is not an lvalue.".to_string())
+                }
+            }
+            offset += len;
         }
         Ok(())
     }
+
+    /// Handles `set` commands that change how we debug.
+    fn set(&mut self, setting: &str) {
+        if let Some(assignment) = setting.strip_prefix('$') {
+            self.set_register(assignment);
+            return;
+        }
+        if let Some(assignment) = setting
+            .strip_prefix("variable ")
+            .or_else(|| setting.strip_prefix("var "))
+        {
+            self.set_variable(assignment);
+            return;
+        }
+        let tokens: Vec<&str> = setting.split_whitespace().collect();
+        match tokens.as_slice() {
+            ["follow-fork-mode", "parent"] => self.fork_policy.follow_child = false,
+            ["follow-fork-mode", "child"] => self.fork_policy.follow_child = true,
+            ["detach-on-fork", "on"] => self.fork_policy.detach_on_fork = true,
+            ["detach-on-fork", "off"] => self.fork_policy.detach_on_fork = false,
+            ["disable-randomization", "on"] => self.disable_randomization = true,
+            ["disable-randomization", "off"] => self.disable_randomization = false,
+            _ => {
+                println!("Usage: set follow-fork-mode parent|child");
+                println!("       set detach-on-fork on|off");
+                println!("       set disable-randomization on|off");
+                return;
+            }
+        }
+        if let Some(inferior) = &mut self.inferior {
+            inferior.set_fork_policy(self.fork_policy);
+        }
+    }
+
+    /// Stops debugging the current inferior, if any. A process we attached to is detached and
+    /// left running; one we started is killed.
+    fn release_inferior(&mut self) {
+        let attached = match &self.inferior {
+            Some(inferior) => inferior.is_attached(),
+            None => return,
+        };
+        if attached {
+            self.detach_inferior();
+        } else {
+            self.inferior.as_mut().unwrap().kill().unwrap();
+            self.inferior = None;
+        }
+    }
+
+    /// Removes our breakpoints from the inferior and lets it run on its own.
+    fn detach_inferior(&mut self) {
+        if let Some(inferior) = &mut self.inferior {
+            let pid = inferior.pid();
+            match inferior.detach(self.breakpoints.inserted()) {
+                Ok(()) => println!("Detaching from process {}", pid),
+                Err(err) => println!("Error detaching from process {}: {}", pid, err),
+            }
+        }
+        self.inferior = None;
+    }
+
+    /// Attaches to the running process `pid`, which should be running the target program.
+    fn attach(&mut self, pid: Pid) {
+        self.release_inferior();
+        // Our debugging information only describes the target, so warn about anything else
+        if let (Ok(exe), Ok(target)) = (
+            fs::canonicalize(format!("/proc/{}/exe", pid)),
+            fs::canonicalize(&self.target),
+        ) {
+            if exe != target {
+                println!(
+                    "Warning: process {} is running {}, not {}",
+                    pid,
+                    exe.display(),
+                    target.display()
+                );
+            }
+        }
+        // Breakpoints go in once we know where the program was loaded
+        match Inferior::attach(pid) {
+            Ok((inferior, status)) => {
+                println!("Attaching to process {}", pid);
+                self.inferior = Some(inferior);
+                self.inferior
+                    .as_mut()
+                    .unwrap()
+                    .set_fork_policy(self.fork_policy);
+                self.load_program();
+                if let Err(err) = self.watchpoints.install(self.inferior.as_mut().unwrap()) {
+                    println!("Could not set watchpoints: {}", err);
+                }
+                self.report_status(status);
+            }
+            Err(err) => println!("Could not attach to process {}: {}", pid, err),
+        }
+    }
+
+    /// Writes a core file of the inferior as it is now, to `path` or else core.<pid>.
+    fn generate_core_file(&self, path: Option<&str>) {
+        let inferior = match &self.inferior {
+            Some(inferior) => inferior,
+            None => {
+                println!("The program is not being run.");
+                return;
+            }
+        };
+        let path = path
+            .map(|path| path.to_string())
+            .unwrap_or_else(|| format!("core.{}", inferior.pid()));
+        // The current thread goes first, as the kernel puts the thread that crashed first
+        let current = inferior.current_thread().tid;
+        let tids = std::iter::once(current).chain(
+            inferior
+                .threads()
+                .iter()
+                .map(|thread| thread.tid)
+                .filter(|tid| *tid != current),
+        );
+        let mut threads = Vec::new();
+        for tid in tids {
+            match inferior.thread_registers(tid) {
+                Ok(regs) => threads.push((tid, regs)),
+                Err(err) => {
+                    println!("Could not read the registers of LWP {}: {}", tid, err);
+                    return;
+                }
+            }
+        }
+        let read_memory = |addr: usize, len: usize| self.read_program_memory(addr, len);
+        match coredump::write(&path, inferior.pid(), &threads, &read_memory) {
+            Ok(()) => println!("Saved corefile {}", path),
+            Err(err) => println!("Could not write core file {}: {}", path, err),
+        }
+    }
+
+    /// Prints the value `func` just returned, according to its declared return type. The
+    /// x86-64 calling convention returns integers, and structs of up to 16 bytes made of them,
+    /// in %rax and %rdx. Larger structs are returned in memory whose address is left in %rax.
+    fn print_return_value(&self, func: &Function) {
+        let (inferior, return_type) = match (&self.inferior, &func.return_type) {
+            (Some(inferior), Some(return_type)) => (inferior, return_type),
+            _ => return,
+        };
+        let regs = match inferior.registers() {
+            Ok(regs) => regs,
+            Err(err) => {
+                println!("Could not read return value: {}", err);
+                return;
+            }
+        };
+        let bytes = if return_type.size > 16 {
+            match self.read_program_memory(regs.rax as usize, return_type.size) {
+                Ok(bytes) => bytes,
+                Err(err) => {
+                    println!("Could not read return value: {}", err);
+                    return;
+                }
+            }
+        } else if self
+            .debug_data_at(func.address)
+            .contains_floating_point(return_type)
+        {
+            // Floating-point values are returned in %xmm0 and %xmm1, which we don't read
+            println!("Value returned from {} is unavailable", func.name);
+            return;
+        } else {
+            let mut bytes = regs.rax.to_le_bytes().to_vec();
+            bytes.extend_from_slice(&regs.rdx.to_le_bytes());
+            bytes.truncate(return_type.size);
+            bytes
+        };
+        println!(
+            "Value returned from {} is {}",
+            func.name,
+            self.debug_data_at(func.address)
+                .format_value(return_type, &bytes)
+        );
+    }
+
+    /// Continues the inferior until it stops for a reason the user cares about, passing over
+    /// breakpoints whose condition is false or whose ignore count hasn't run out, watchpoints
+    /// whose memory was written with the value it already had, and signals set to `nostop`.
+    fn continue_inferior(&mut self) -> Result<Status, nix::Error> {
+        loop {
+            let inferior = self.inferior.as_mut().unwrap();
+            let status = inferior.resume(None, self.breakpoints.inserted())?;
+            if let Some(status) = self.check_stop(status)? {
+                return Ok(status);
+            }
+        }
+    }
+
+    /// Decides whether the inferior, having been resumed and stopped with `status`, stays
+    /// stopped as far as the user is concerned. Returns None if it should be resumed again, as
+    /// `continue_inferior` describes, or after an internal breakpoint or an exec.
+    fn check_stop(&mut self, status: Status) -> Result<Option<Status>, nix::Error> {
+        let inferior = self.inferior.as_mut().unwrap();
+        let rip = match status {
+            Status::Stopped(Signal::SIGTRAP, rip) => rip,
+            Status::Exec(_) => {
+                self.handle_exec();
+                return Ok(None);
+            }
+            Status::Stopped(signal, _) => {
+                let policy = self.signals.get(signal);
+                inferior.set_pending_signal(if policy.pass { Some(signal) } else { None });
+                if policy.stop {
+                    return Ok(Some(status));
+                }
+                if policy.print {
+                    println!("Child received signal {}", signal);
+                }
+                return Ok(None);
+            }
+            other => return Ok(Some(other)),
+        };
+        // A write just before a breakpoint traps with %rip already on it, so DR6 is checked
+        // whether or not we are at one
+        let triggered = self.watchpoints.triggered(inferior)?;
+        let watch_stop = !triggered.is_empty() && self.watchpoints_changed(&triggered)?;
+        if !self.breakpoints.inserted().contains_key(&rip) {
+            // A trap that is neither ours nor a watchpoint's comes from the program itself
+            if triggered.is_empty() || watch_stop {
+                return Ok(Some(status));
+            }
+            return Ok(None);
+        }
+        if self.at_rendezvous(rip) {
+            self.handle_rendezvous();
+        }
+        let stopping = self.breakpoints_hit(rip);
+        if stopping.is_empty() && !watch_stop {
+            return Ok(None);
+        }
+        for id in stopping {
+            println!("Breakpoint {}, {:#x}", id, rip);
+        }
+        Ok(Some(status))
+    }
+
+    /// Returns true if `addr` is where we keep the rendezvous breakpoint.
+    fn at_rendezvous(&self, addr: usize) -> bool {
+        self.rendezvous
+            .as_ref()
+            .map(|rendezvous| rendezvous.breakpoint)
+            == Some(addr)
+    }
+
+    /// Executes a single machine instruction. Returns None once it has, or the status if the
+    /// inferior stopped for something the user cares about, like a watchpoint whose memory the
+    /// instruction changed. Landing on the rendezvous breakpoint counts as hitting it, since the
+    /// next resume steps over its trap rather than executing it. A signal that arrives instead
+    /// is handled according to `handle`, as when continuing; its handler, if any, is run through
+    /// rather than stepped into.
+    fn single_step(&mut self) -> Result<Option<Status>, nix::Error> {
+        let inferior = self.inferior.as_mut().unwrap();
+        if let Some(signal) = inferior.pending_signal() {
+            if inferior.catches(signal) {
+                // Come back to the instruction once the handler returns
+                let regs = inferior.registers()?;
+                if let Some(status) = self.run_to_address(regs.rip as usize, regs.rsp as usize)? {
+                    return Ok(Some(status));
+                }
+            }
+        }
+        let inferior = self.inferior.as_mut().unwrap();
+        let status = inferior.step_instruction(self.breakpoints.inserted())?;
+        let rip = match status {
+            Status::Stopped(Signal::SIGTRAP, rip) => rip,
+            Status::Stopped(signal, _) => {
+                let policy = self.signals.get(signal);
+                inferior.set_pending_signal(if policy.pass { Some(signal) } else { None });
+                if policy.stop {
+                    return Ok(Some(status));
+                }
+                if policy.print {
+                    println!("Child received signal {}", signal);
+                }
+                // The instruction hasn't run yet
+                return self.single_step();
+            }
+            other => return Ok(Some(other)),
+        };
+        // The step sets a bit in DR6 too, so it is read and cleared after every one
+        let triggered = self.watchpoints.triggered(inferior)?;
+        if !triggered.is_empty() && self.watchpoints_changed(&triggered)? {
+            return Ok(Some(status));
+        }
+        if self.at_rendezvous(rip) {
+            self.handle_rendezvous();
+        }
+        Ok(None)
+    }
+
+    /// Returns the status of an inferior that has finished a step: stopped by the trap at
+    /// wherever it got to.
+    fn step_done(&self) -> Result<Status, nix::Error> {
+        let rip = self.inferior.as_ref().unwrap().registers()?.rip as usize;
+        Ok(Status::Stopped(Signal::SIGTRAP, rip))
+    }
+
+    /// Executes a single machine instruction, like `single_step`, except that a call is run
+    /// through until it returns.
+    fn next_instruction(&mut self) -> Result<Option<Status>, nix::Error> {
+        let regs = self.inferior.as_ref().unwrap().registers()?;
+        let (prev_rip, prev_rsp) = (regs.rip as usize, regs.rsp as usize);
+        if let Some(status) = self.single_step()? {
+            return Ok(Some(status));
+        }
+        let inferior = self.inferior.as_ref().unwrap();
+        match inferior.call_return_address(prev_rip, prev_rsp)? {
+            Some(ret_addr) => self.run_to_address(ret_addr, prev_rsp),
+            None => Ok(None),
+        }
+    }
+
+    /// Continues the inferior until it reaches `addr` with its stack pointer at or above
+    /// `frame_rsp`, using a temporary breakpoint that is removed again afterwards. Returns None
+    /// once there, or the status if something else stops the inferior first, as it would stop
+    /// `continue_inferior`. A breakpoint of the user's at `addr` counts as something else.
+    fn run_to_address(
+        &mut self,
+        addr: usize,
+        frame_rsp: usize,
+    ) -> Result<Option<Status>, nix::Error> {
+        let inferior = self.inferior.as_mut().unwrap();
+        self.breakpoints.add_internal(addr, inferior)?;
+        let stopped = loop {
+            let inferior = self.inferior.as_mut().unwrap();
+            let status = inferior.resume(None, self.breakpoints.inserted())?;
+            let reached = match status {
+                // A recursive call may hit the same address in a deeper frame
+                Status::Stopped(Signal::SIGTRAP, rip) if rip == addr => {
+                    inferior.registers()?.rsp as usize >= frame_rsp
+                }
+                // The new program doesn't have our breakpoint
+                Status::Exec(_) => break Some(status),
+                _ => false,
+            };
+            match self.check_stop(status)? {
+                Some(status) => break Some(status),
+                None if reached => break None,
+                None => {}
+            }
+        };
+        if let None | Some(Status::Stopped(..)) = stopped {
+            let inferior = self.inferior.as_mut().unwrap();
+            self.breakpoints.delete_internal(addr, inferior)?;
+        }
+        Ok(stopped)
+    }
+
+    /// Runs the inferior until it reaches a different source line in the current frame. Calls
+    /// are stepped over, unless `step_into` is set and the callee has debugging information, in
+    /// which case we stop at the first line of its body. If the current function returns,
+    /// stepping carries on in the caller.
+    fn step_line(&mut self, step_into: bool) -> Result<Status, nix::Error> {
+        let mut regs = self.inferior.as_ref().unwrap().registers()?;
+        let rip = regs.rip as usize;
+        let mut start_line = match self.debug_data_at(rip).get_line_from_addr(rip) {
+            Some(line) => line,
+            // Without line information, the best we can do is a single instruction
+            None => return self.single_step()?.map_or_else(|| self.step_done(), Ok),
+        };
+        let mut func_range = self
+            .debug_data_at(rip)
+            .get_function_containing(rip)
+            .map(|func| (func.address, func.address + func.text_length));
+        loop {
+            let prev_rip = regs.rip as usize;
+            let prev_rsp = regs.rsp as usize;
+            if let Some(status) = self.single_step()? {
+                return Ok(status);
+            }
+            let inferior = self.inferior.as_ref().unwrap();
+            regs = inferior.registers()?;
+            let rip = regs.rip as usize;
+
+            if let Some(ret_addr) = inferior.call_return_address(prev_rip, prev_rsp)? {
+                let body = if step_into {
+                    let debug_data = self.debug_data_at(rip);
+                    debug_data
+                        .get_function_containing(rip)
+                        .and_then(|func| debug_data.get_function_body_addr(func))
+                } else {
+                    None
+                };
+                if let Some(body) = body {
+                    return Ok(self
+                        .run_to_address(body, 0)?
+                        .unwrap_or(Status::Stopped(Signal::SIGTRAP, body)));
+                }
+                if let Some(status) = self.run_to_address(ret_addr, prev_rsp)? {
+                    return Ok(status);
+                }
+                regs = self.inferior.as_ref().unwrap().registers()?;
+                continue;
+            }
+
+            let in_frame = match func_range {
+                Some((low, high)) => low <= rip && rip < high,
+                None => true,
+            };
+            let line = self.debug_data_at(rip).get_line_from_addr(rip);
+            if !in_frame {
+                // We returned from the function; finish the current line in the caller
+                match line {
+                    Some(line) => start_line = line,
+                    None => return Ok(Status::Stopped(Signal::SIGTRAP, rip)),
+                }
+                func_range = self
+                    .debug_data_at(rip)
+                    .get_function_containing(rip)
+                    .map(|func| (func.address, func.address + func.text_length));
+                continue;
+            }
+            if let Some(line) = line {
+                if line.number != start_line.number || line.file != start_line.file {
+                    return Ok(Status::Stopped(Signal::SIGTRAP, rip));
+                }
+            }
+        }
+    }
+
+    /// Checks the conditions of the enabled breakpoints at `addr`, where the inferior has just
+    /// trapped, and returns the IDs of those that should stop it.
+    fn breakpoints_hit(&mut self, addr: usize) -> Vec<usize> {
+        let mut stopping = Vec::new();
+        for (id, condition) in self.breakpoints.enabled_at(addr) {
+            if let Some(Condition { expr, .. }) = condition {
+                match expr.evaluate(&|name: &str| self.variable_as_i64(name)) {
+                    Ok(0) => continue,
+                    Ok(_) => {}
+                    Err(err) => {
+                        println!("Error in testing condition for breakpoint {}:", id);
+                        println!("{}", err);
+                        stopping.push(id);
+                        continue;
+                    }
+                }
+            }
+            if self.breakpoints.record_hit(id) {
+                stopping.push(id);
+            }
+        }
+        stopping
+    }
+
+    /// Reports the watchpoints in `ids` whose memory changed since we last looked, returning
+    /// false if none did.
+    fn watchpoints_changed(&mut self, ids: &[usize]) -> Result<bool, nix::Error> {
+        let inferior = self.inferior.as_ref().unwrap();
+        let mut changed = false;
+        for id in ids {
+            let wp = self.watchpoints.get_mut(*id).unwrap();
+            let new_value = inferior.read_memory(wp.addr, wp.len)?;
+            if wp.old_value.as_ref() == Some(&new_value) {
+                continue;
+            }
+            wp.hit_count += 1;
+            let old_value = wp.old_value.replace(new_value);
+            let wp = self.watchpoints.get(*id).unwrap();
+            println!("Hardware watchpoint {}: {}", wp.id, wp.expression);
+            println!();
+            if let Some(old_value) = old_value {
+                println!("Old value = {}", self.format_watched_value(wp, &old_value));
+            }
+            println!(
+                "New value = {}",
+                self.format_watched_value(wp, wp.old_value.as_ref().unwrap())
+            );
+            changed = true;
+        }
+        Ok(changed)
+    }
+
+    fn format_watched_value(&self, wp: &Watchpoint, bytes: &[u8]) -> String {
+        match &wp.var_type {
+            Some(var_type) => self.debug_data.format_value(var_type, bytes),
+            None => {
+                let mut raw = [0u8; 8];
+                raw[..bytes.len()].copy_from_slice(bytes);
+                format!("{:#x}", u64::from_le_bytes(raw))
+            }
+        }
+    }
+
+    /// Sets a hardware watchpoint on a variable or on `*<address>`. Locals can only be watched
+    /// while the inferior is stopped in their function, and stay watched after it returns.
+    fn add_watchpoint(&mut self, expression: String) {
+        if self.watchpoints.is_full() {
+            println!(
+                "Too many hardware watchpoints (at most {}).",
+                watchpoint::MAX_WATCHPOINTS
+            );
+            return;
+        }
+        let (addr, len, var_type) = if let Some(addr) = expression.strip_prefix('*') {
+            let addr = match parse_addr(addr) {
+                Some(addr) => addr,
+                None => {
+                    println!("Invalid address");
+                    return;
+                }
+            };
+            // As in gdb, the address is taken to point at an int, unless it isn't aligned for one
+            let len = [4usize, 2, 1].iter().find(|len| addr % **len == 0).unwrap();
+            (addr, *len, None)
+        } else {
+            match self.locate_variable(&expression) {
+                Ok((addr, var_type)) => (addr, var_type.size, Some(var_type)),
+                Err(err) => {
+                    println!("{}", err);
+                    return;
+                }
+            }
+        };
+        if !watchpoint::can_watch(addr, len) {
+            println!(
+                "Cannot watch {}: only aligned values of 1, 2, 4 or 8 bytes can be watched.",
+                expression
+            );
+            return;
+        }
+        let id = self.breakpoints.allocate_id();
+        match self.watchpoints.add(
+            id,
+            expression.clone(),
+            addr,
+            len,
+            var_type,
+            self.inferior.as_mut(),
+        ) {
+            Ok(()) => println!("Hardware watchpoint {}: {}", id, expression),
+            Err(err) => println!("Could not set watchpoint: {}", err),
+        }
+    }
+
+    /// Returns the address and type of the variable `name`, which must live in memory.
+    fn locate_variable(&self, name: &str) -> Result<(usize, Type), String> {
+        let state = match self.state() {
+            Some(state) => state,
+            // Before the program starts, only globals have an address
+            None => {
+                return match self.debug_data.get_global_variable(name) {
+                    Some(Variable {
+                        location: Location::Address(addr),
+                        entity_type,
+                        ..
+                    }) => Ok((*addr, entity_type.clone())),
+                    Some(_) => Err(format!(
+                        "\"{}\" has no address until the program runs.",
+                        name
+                    )),
+                    None => Err(format!("No symbol \"{}\" in current context.", name)),
+                };
+            }
+        };
+        let regs = state.registers().map_err(|err| err.to_string())?;
+        let (var, func) = self
+            .find_variable(regs.rip as usize, name)
+            .ok_or_else(|| format!("No symbol \"{}\" in current context.", name))?;
+        let pieces = self
+            .with_variable_pieces(var, func, |pieces, _| Ok(pieces.to_vec()))
+            .map_err(|err| format!("Could not locate {}: {}", name, err))?;
+        match pieces.as_slice() {
+            [Piece {
+                location: PieceLocation::Memory(addr),
+                ..
+            }] => Ok((*addr, var.entity_type.clone())),
+            _ => Err(format!("\"{}\" is not in memory.", name)),
+        }
+    }
+
+    /// Looks up `name` among the variables of the function containing `rip`, falling back to
+    /// global variables. Also returns the function, if any, for evaluating the location.
+    fn find_variable(&self, rip: usize, name: &str) -> Option<(&Variable, Option<&Function>)> {
+        let func = self.debug_data.get_function_containing(rip);
+        let local = func.and_then(|func| func.variables.iter().find(|var| var.name == name));
+        let var = local.or_else(|| self.debug_data.get_global_variable(name))?;
+        Some((var, func))
+    }
+
+    /// Reads the current value of a scalar variable as an integer, for use in expressions.
+    fn variable_as_i64(&self, name: &str) -> Result<i64, String> {
+        let state = self.state().unwrap();
+        let regs = state.registers().map_err(|err| err.to_string())?;
+        let (var, func) = self
+            .find_variable(regs.rip as usize, name)
+            .ok_or_else(|| format!("No symbol \"{}\" in current context.", name))?;
+        let bytes = match self.read_variable(var, func) {
+            Ok(bytes) => bytes,
+            Err(EvalError::OptimizedOut) => return Err(format!("\"{}\" is optimized out", name)),
+            Err(err) => return Err(format!("Could not read {}: {}", name, err)),
+        };
+        self.debug_data
+            .value_as_i64(&var.entity_type, &bytes)
+            .ok_or_else(|| format!("\"{}\" is not a scalar value", name))
+    }
+
+    /// Looks up `name` among the variables of the function the inferior is stopped in, falling
+    /// back to global variables, and prints its current value.
+    fn print_variable(&self, name: &str) {
+        let state = self.state().unwrap();
+        let regs = match state.registers() {
+            Ok(regs) => regs,
+            Err(err) => {
+                println!("Could not read registers: {}", err);
+                return;
+            }
+        };
+        let (var, func) = match self.find_variable(regs.rip as usize, name) {
+            Some(found) => found,
+            None => {
+                println!("No symbol \"{}\" in current context.", name);
+                return;
+            }
+        };
+        match self.read_variable(var, func) {
+            Ok(bytes) => println!(
+                "{} = {}",
+                var.name,
+                self.debug_data.format_value(&var.entity_type, &bytes)
+            ),
+            Err(EvalError::OptimizedOut) => println!("{} = <optimized out>", var.name),
+            Err(err) => println!("Could not read {}: {}", var.name, err),
+        }
+    }
+
+    /// Reads the current value of `var` by evaluating its DWARF location in the innermost frame.
+    /// `func` is the function whose frame base the location may be relative to.
+    fn read_variable(&self, var: &Variable, func: Option<&Function>) -> Result<Vec<u8>, EvalError> {
+        self.with_variable_pieces(var, func, |pieces, ctx| {
+            dwarf_expr::read_pieces(pieces, var.entity_type.size, ctx)
+        })
+    }
+
+    /// Evaluates the DWARF location of `var` in the innermost frame and hands the resulting
+    /// pieces, along with the evaluation context, to `f`.
+    fn with_variable_pieces<T>(
+        &self,
+        var: &Variable,
+        func: Option<&Function>,
+        f: impl FnOnce(&[Piece], &EvalContext) -> Result<T, EvalError>,
+    ) -> Result<T, EvalError> {
+        let state = self.state().unwrap();
+        let regs = state.registers()?;
+        let read_memory = |addr: usize, len: usize| state.read_memory(addr, len);
+        let mut ctx = EvalContext {
+            regs: &regs,
+            cfa: self.unwinder.cfa(&regs, &read_memory),
+            frame_base: None,
+            read_memory: &read_memory,
+            load_bias: self.debug_data.load_bias(),
+        };
+        if let Some(frame_base) = func.and_then(|func| func.frame_base.as_ref()) {
+            ctx.frame_base = Some(dwarf_expr::evaluate_frame_base(frame_base, &ctx)?);
+        }
+        let pieces = dwarf_expr::evaluate_location(&var.location, regs.rip as usize, &ctx)?;
+        f(&pieces, &ctx)
+    }
+
+    fn print_breakpoints(&self) {
+        if self.breakpoints.iter().next().is_none() && self.watchpoints.iter().next().is_none() {
+            println!("No breakpoints.");
+            return;
+        }
+        println!("Num     Enb What");
+        for bp in self.breakpoints.iter() {
+            println!(
+                "{:<7} {:<3} {}",
+                bp.id,
+                if bp.enabled { "y" } else { "n" },
+                bp
+            );
+            if let Some(condition) = &bp.condition {
+                println!("        stop only if {}", condition.source);
+            }
+            if bp.hit_count > 0 {
+                println!(
+                    "        breakpoint already hit {} time{}",
+                    bp.hit_count,
+                    if bp.hit_count == 1 { "" } else { "s" }
+                );
+            }
+            if bp.ignore_count > 0 {
+                println!(
+                    "        Will ignore next {} crossings of breakpoint.",
+                    bp.ignore_count
+                );
+            }
+        }
+        for wp in self.watchpoints.iter() {
+            println!("{:<7} {:<3} hw watchpoint {}", wp.id, "y", wp.expression);
+            if wp.hit_count > 0 {
+                println!(
+                    "        breakpoint already hit {} time{}",
+                    wp.hit_count,
+                    if wp.hit_count == 1 { "" } else { "s" }
+                );
+            }
+        }
+    }
+
     pub fn run(&mut self) {
         loop {
-            match self.get_next_command() {
+            let command = self.get_next_command();
+            match command {
                 DebuggerCommand::Run(args) => {
-                    if self.inferior.is_some() {
-                        self.inferior.as_mut().unwrap().kill().unwrap();
-                        self.inferior = None;
-                    }
-                    if let Some(inferior) =
-                        Inferior::new(&self.target, &args, &mut self.break_point)
-                    {
+                    self.release_inferior();
+                    // Breakpoints go in once we know where the program was loaded
+                    let inferior = Inferior::new(&self.target, &args, self.disable_randomization);
+                    if let Some(inferior) = inferior {
                         self.inferior = Some(inferior);
-                        match self.inferior.as_mut().unwrap().continue_exec(None).unwrap() {
-                            Status::Exited(exit_code) => {
-                                println!("Child exited (status {}) ", exit_code);
-                                self.inferior = None;
-                            }
-                            Status::Signaled(signal) => {
-                                println!("Child exited due to signal {}", signal);
-                                self.inferior = None;
-                            }
-                            Status::Stopped(signal, rip) => {
-                                println!(
-                                    "Child stopped due to signal {} at address {}",
-                                    signal, rip
-                                );
-                                let file = self.debug_data.get_line_from_addr(rip);
-                                let func = self.debug_data.get_function_from_addr(rip);
-                                if file.is_some() && func.is_some() {
-                                    let file = file.unwrap();
-                                    println!("Stopped at {}({})", func.unwrap(), file);
-                                    let path = file.file;
-                                    let number = file.number;
-                                    self.print_line_code(&path, &number).unwrap();
-                                }
-                            }
+                        self.inferior
+                            .as_mut()
+                            .unwrap()
+                            .set_fork_policy(self.fork_policy);
+                        self.load_program();
+                        if let Err(err) = self.watchpoints.install(self.inferior.as_mut().unwrap())
+                        {
+                            println!("Could not set watchpoints: {}", err);
+                        }
+                        match self.continue_inferior() {
+                            Ok(status) => self.report_status(status),
+                            Err(err) => println!("Error continuing inferior: {}", err),
                         }
                     } else {
                         println!("Error starting subprocess");
                     }
                 }
+                DebuggerCommand::Attach(pid) => self.attach(Pid::from_raw(pid)),
+                DebuggerCommand::Detach => {
+                    if self.inferior.is_some() {
+                        self.detach_inferior();
+                    } else {
+                        println!("The program is not being run.");
+                    }
+                }
                 DebuggerCommand::Continue => {
-                    if let Some(inferior) = &mut self.inferior {
-                        match inferior.continue_exec(None).unwrap() {
-                            Status::Exited(exit_code) => {
-                                println!("Child exited (status {}) ", exit_code);
-                                self.inferior = None;
-                            }
-                            Status::Signaled(signal) => {
-                                println!("Child exited due to signal {}", signal);
-                                self.inferior = None;
-                            }
-                            Status::Stopped(signal, rip) => {
-                                match signal {
-                                    Signal::SIGTRAP => (),
-
-                                    _ => (),
-                                }
-                                println!(
-                                    "Child stopped due to signal {} at address {}",
-                                    signal, rip
-                                );
-                                let file = self.debug_data.get_line_from_addr(rip);
-                                let func = self.debug_data.get_function_from_addr(rip);
-                                if file.is_some() && func.is_some() {
-                                    let file = file.unwrap();
-                                    println!("Stopped at {}({})", func.unwrap(), file);
-                                    let path = file.file;
-                                    let number = file.number;
-                                    self.print_line_code(&path, &number).unwrap();
-                                }
-                            }
+                    if self.inferior.is_some() {
+                        match self.continue_inferior() {
+                            Ok(status) => self.report_status(status),
+                            Err(err) => println!("Error continuing inferior: {}", err),
                         }
                     } else {
                         println!("No inferior to continue");
                     }
                 }
-                DebuggerCommand::Backtrace => {
-                    if self.inferior.is_none() {
-                        println!("No inferior to backtrace");
-                    } else {
-                        let inferior = self.inferior.as_ref().unwrap();
-                        inferior.print_backtrace(&self.debug_data).unwrap();
+                DebuggerCommand::Backtrace => match self.state() {
+                    Some(state) => state
+                        .print_backtrace(&self.debug_data, &self.unwinder)
+                        .unwrap(),
+                    None => println!("No inferior to backtrace"),
+                },
+                DebuggerCommand::BreakPoint(addr, condition) => {
+                    let condition = match condition {
+                        Some(source) => match expr::parse(&source) {
+                            Ok(expr) => Some(Condition { source, expr }),
+                            Err(err) => {
+                                println!("Invalid condition \"{}\": {}", source, err);
+                                continue;
+                            }
+                        },
+                        None => None,
+                    };
+                    let resolved =
+                        resolve_breakpoint(&self.debug_data, &self.shared_libraries, &addr);
+                    let (break_point_addr, function, line) = match resolved {
+                        Ok(resolved) => resolved,
+                        Err(err) => {
+                            println!("{}", err);
+                            // Libraries may not be loaded yet, so wait for them
+                            if let Some((library, _)) = addr.split_once(':') {
+                                if names_library(library) {
+                                    let id = self.breakpoints.add_pending(addr.clone(), condition);
+                                    println!("Breakpoint {} ({}) pending.", id, addr);
+                                }
+                            }
+                            continue;
+                        }
+                    };
+                    match self.breakpoints.add(
+                        break_point_addr,
+                        addr,
+                        function,
+                        line,
+                        condition,
+                        self.inferior.as_mut(),
+                    ) {
+                        Ok(id) => println!("Set breakpoint {} at {:#x}", id, break_point_addr),
+                        Err(_) => println!("Invalid break point address {:#x}", break_point_addr),
                     }
                 }
-                DebuggerCommand::BreakPoint(addr) => {
-                    let break_point_addr;
-                    if let Some(addr_) = addr.strip_prefix("*") {
-                        if let Some(addr) = self.parse_addr(addr_) {
-                            break_point_addr = addr;
-                        } else {
-                            println!("Invalid address");
-                            continue;
+                DebuggerCommand::Delete(id) => {
+                    let result = match id {
+                        Some(id) if self.watchpoints.get(id).is_some() => {
+                            self.watchpoints.delete(id, self.inferior.as_mut())
                         }
-                    } else if let Ok(line_num) = addr.parse::<usize>() {
-                        if let Some(line_addr) = self.debug_data.get_addr_for_line(None, line_num) {
-                            break_point_addr = line_addr;
-                        } else {
-                            println!("Could not find address for line {}", line_num);
+                        Some(id) if self.breakpoints.get(id).is_none() => {
+                            println!("No breakpoint number {}.", id);
                             continue;
                         }
-                    } else if let Some(func_addr) =
-                        self.debug_data.get_addr_for_function(None, &addr)
+                        Some(id) => self.breakpoints.delete(id, self.inferior.as_mut()),
+                        None => {
+                            let result = self.watchpoints.clear(self.inferior.as_mut());
+                            result.and(self.breakpoints.clear(self.inferior.as_mut()))
+                        }
+                    };
+                    if let Err(err) = result {
+                        println!("Error removing breakpoint: {}", err);
+                    }
+                }
+                DebuggerCommand::Enable(id) | DebuggerCommand::Disable(id) => {
+                    let enabled = matches!(command, DebuggerCommand::Enable(_));
+                    if self.breakpoints.get(id).is_none() {
+                        println!("No breakpoint number {}.", id);
+                    } else if let Err(err) =
+                        self.breakpoints
+                            .set_enabled(id, enabled, self.inferior.as_mut())
                     {
-                        break_point_addr = func_addr;
-                    } else {
-                        println!("Could not find address for correct usage {}", addr);
+                        println!("Error updating breakpoint: {}", err);
+                    }
+                }
+                DebuggerCommand::Ignore(id, count) => {
+                    if self.breakpoints.get(id).is_none() {
+                        println!("No breakpoint number {}.", id);
                         continue;
                     }
-                    if self.inferior.is_some() {
-                        if let Ok(inst) = self
-                            .inferior
-                            .as_mut()
-                            .unwrap()
-                            .write_byte(break_point_addr, 0xcc)
-                        {
-                            println!(
-                                "Set breakpoint {} at {:#x}",
-                                self.break_point.len(),
-                                break_point_addr
-                            );
-                            self.break_point.insert(break_point_addr, inst);
-                        } else {
-                            println!("Invalid break point address {:#x}", break_point_addr);
+                    self.breakpoints.set_ignore_count(id, count);
+                    match count {
+                        0 => println!("Will stop next time breakpoint {} is reached.", id),
+                        1 => println!("Will ignore next crossing of breakpoint {}.", id),
+                        _ => println!("Will ignore next {} crossings of breakpoint {}.", count, id),
+                    }
+                }
+                DebuggerCommand::Thread(id) => {
+                    let inferior = match &mut self.inferior {
+                        Some(inferior) => inferior,
+                        None => {
+                            println!("No thread selected");
+                            continue;
+                        }
+                    };
+                    match id {
+                        Some(id) if !inferior.select_thread(id) => {
+                            println!("Invalid thread ID: {}", id);
+                        }
+                        Some(_) => {
+                            let thread = inferior.current_thread();
+                            println!("[Switching to thread {} (LWP {})]", thread.id, thread.tid);
+                        }
+                        None => {
+                            let thread = inferior.current_thread();
+                            println!("[Current thread is {} (LWP {})]", thread.id, thread.tid);
                         }
-                    } else {
-                        println!(
-                            "Set breakpoint {} at {:#x}",
-                            self.break_point.len(),
-                            break_point_addr
-                        );
-                        self.break_point.insert(break_point_addr, 0);
                     }
                 }
+                DebuggerCommand::Info(what, args) => match what.as_str() {
+                    "b" | "break" | "breakpoints" => self.print_breakpoints(),
+                    "signals" | "handle" => self.print_signals(None),
+                    "r" | "registers" => self.print_registers(&args),
+                    "shared" | "sharedlibrary" => self.print_shared_libraries(),
+                    "threads" => match &self.inferior {
+                        Some(inferior) => inferior
+                            .print_threads(&|pc: usize| self.debug_data_at(pc), &self.unwinder),
+                        None => println!("No threads."),
+                    },
+                    _ => println!("Undefined info command: \"{}\".", what),
+                },
                 DebuggerCommand::Quit => {
+                    self.release_inferior();
+                }
+                DebuggerCommand::Exit => {
+                    self.release_inferior();
+                    println!("Exiting debugger");
+                    return;
+                }
+                DebuggerCommand::Next => {
                     if self.inferior.is_some() {
-                        self.inferior.as_mut().unwrap().kill().unwrap();
-                        self.inferior = None;
+                        match self.step_line(false) {
+                            Ok(status) => self.report_status(status),
+                            Err(err) => println!("Error stepping inferior: {}", err),
+                        }
+                    } else {
+                        println!("No inferior to step");
                     }
                 }
-                DebuggerCommand::Exit => {
+                DebuggerCommand::Step => {
                     if self.inferior.is_some() {
-                        self.inferior.as_mut().unwrap().kill().unwrap();
-                        self.inferior = None;
+                        match self.step_line(true) {
+                            Ok(status) => self.report_status(status),
+                            Err(err) => println!("Error stepping inferior: {}", err),
+                        }
+                    } else {
+                        println!("No inferior to step");
+                    }
+                }
+                DebuggerCommand::StepInstruction => self.step_instruction(false),
+                DebuggerCommand::NextInstruction => self.step_instruction(true),
+                DebuggerCommand::Finish => {
+                    if let Some(inferior) = &self.inferior {
+                        let frames = inferior.backtrace(&self.unwinder).unwrap_or_default();
+                        if frames.len() < 2 {
+                            println!("\"finish\" not meaningful in the outermost frame.");
+                            continue;
+                        }
+                        let func = self
+                            .debug_data_at(frames[0].pc)
+                            .get_function_containing(frames[0].pc)
+                            .cloned();
+                        // Once `ret` pops the return address, %rsp is back at the CFA of the
+                        // finished frame
+                        match self.run_to_address(frames[1].pc, frames[0].cfa) {
+                            Ok(None) => {
+                                self.report_status(Status::Stopped(Signal::SIGTRAP, frames[1].pc));
+                                if let Some(func) = func {
+                                    self.print_return_value(&func);
+                                }
+                            }
+                            Ok(Some(status)) => self.report_status(status),
+                            Err(err) => println!("Error finishing function: {}", err),
+                        }
+                    } else {
+                        println!("No inferior to finish");
                     }
-                    println!("Exiting debugger");
-                    return;
                 }
-                DebuggerCommand::Next => {}
-                DebuggerCommand::Print => {}
+                DebuggerCommand::Print(name) => {
+                    if name.is_empty() {
+                        println!("Usage: print <variable>");
+                    } else if self.state().is_none() {
+                        println!("No inferior to read variables from");
+                    } else {
+                        self.print_variable(&name);
+                    }
+                }
+                DebuggerCommand::Watch(expression) => {
+                    if expression.is_empty() {
+                        println!("Usage: watch <variable> | watch *<address>");
+                    } else {
+                        self.add_watchpoint(expression);
+                    }
+                }
+                DebuggerCommand::List(location) => self.list(location.as_deref()),
+                DebuggerCommand::Directory(dirs) => self.directory(&dirs),
+                DebuggerCommand::Disassemble(name) => self.disassemble(name.as_deref()),
+                DebuggerCommand::Examine(spec, location) => self.examine(&spec, &location),
+                DebuggerCommand::GenerateCore(path) => self.generate_core_file(path.as_deref()),
+                DebuggerCommand::Set(setting) => self.set(&setting),
+                DebuggerCommand::Handle(name, keywords) => match signals::parse_signal(&name) {
+                    Some(signal) => match self.signals.handle(signal, &keywords) {
+                        Ok(()) => self.print_signals(Some(signal)),
+                        Err(err) => println!("{}", err),
+                    },
+                    None => println!("Unknown signal \"{}\"", name),
+                },
                 DebuggerCommand::Help => {
                     println!("h | help - ask for help");
                     println!("r | run - run new program");
+                    println!("attach <pid> - debug a running process of the target program");
+                    println!("detach - remove breakpoints and let the process run on its own");
                     println!("c | cont | continue - continue code execution");
-                    println!("b | break - set a breakpoint");
+                    println!("b | break <loc> [if <cond>] - set a (conditional) breakpoint");
+                    println!("    <loc>: *<addr>, <line>, <func>, <file>:<line> or <file>:<func>");
+                    println!("    <file> may be a shared library, e.g. libfoo.so:init");
+                    println!("ignore N COUNT - pass over the next COUNT hits of breakpoint N");
+                    println!("i | info breakpoints - list breakpoints");
+                    println!("i | info threads - list threads");
+                    println!("i | info sharedlibrary - list loaded shared libraries");
+                    println!("i | info registers [reg...] - show the current thread's registers");
+                    println!("t | thread [N] - show or switch the current thread");
+                    println!("d | delete [N] - delete breakpoint N, or all breakpoints");
+                    println!("dis | disable N - disable breakpoint N");
+                    println!("enable N - enable breakpoint N");
                     println!("n | next - single step execution");
-                    println!("p | print - print the variables");
+                    println!("s | step - single step execution, entering function calls");
+                    println!("si | stepi - execute one machine instruction");
+                    println!("ni | nexti - execute one machine instruction, stepping over calls");
+                    println!("fin | finish - run until the current function returns");
+                    println!("p | print <var> - print the value of a variable");
+                    println!("watch <var> | watch *<addr> - stop when a value is written");
+                    println!("handle <sig> [no]stop|[no]print|[no]pass - set what a signal does");
+                    println!("i | info signals - list what is done on each signal");
+                    println!("x/NFU <addr|var> - examine memory (format x d c s i, unit b h w g)");
+                    println!("l | list [N|func|file:N] - show source around a line or function");
+                    println!("dir | directory <path>... - add directories to search for sources");
+                    println!("disas | disassemble [func] - disassemble a function");
+                    println!("gcore | generate-core-file [file] - save a core file of the program");
+                    println!("set var <var> = <expr> - change a variable");
+                    println!("set $<reg> = <expr> - change a register of the current thread");
+                    println!("set follow-fork-mode parent|child - process to follow at a fork");
+                    println!("set detach-on-fork on|off - whether to let go of the other process");
+                    println!("set disable-randomization on|off - turn off ASLR for new processes");
                     println!("q | quit - quit the program");
                     println!("e | exit - quit the debugger");
-                    println!("Some command is unimplmented.{{list}}");
                 }
             }
         }
     }
 
-    fn parse_addr(&self, addr: &str) -> Option<usize> {
-        let addr_without_0x = if addr.to_lowercase().starts_with("0x") {
-            &addr[2..]
-        } else {
-            &addr
-        };
-        usize::from_str_radix(addr_without_0x, 16).ok()
-    }
-
     /// This function prompts the user to enter a command, and continues re-prompting until the user
     /// enters a valid command. It uses DebuggerCommand::from_tokens to do the command parsing.
     ///
@@ -284,3 +2044,129 @@ impl Debugger {
         }
     }
 }
+
+fn parse_addr(addr: &str) -> Option<usize> {
+    let addr_without_0x = if addr.to_lowercase().starts_with("0x") {
+        &addr[2..]
+    } else {
+        &addr
+    };
+    usize::from_str_radix(addr_without_0x, 16).ok()
+}
+
+/// Finds the address of a breakpoint location: `*<address>`, a line number, or a function name.
+fn resolve_location(debug_data: &DwarfData, location: &str) -> Result<usize, String> {
+    if let Some(addr) = location.strip_prefix("*") {
+        return parse_addr(addr).ok_or_else(|| "Invalid address".to_string());
+    }
+    let (file, name) = match location.rsplit_once(':') {
+        Some((file, name)) => (Some(resolve_file(debug_data, file)?), name),
+        None => (None, location),
+    };
+    if let Ok(line_num) = name.parse::<usize>() {
+        return debug_data
+            .get_addr_for_line(file, line_num)
+            .ok_or_else(|| format!("Could not find address for line {}", line_num));
+    }
+    if file.is_none() {
+        // Static functions in different files may share a name
+        let files = debug_data.find_function_files(name);
+        if files.len() > 1 {
+            return Err(format!(
+                "Function \"{}\" is defined in several files ({}); use <file>:{}",
+                name,
+                files.join(", "),
+                name
+            ));
+        }
+    }
+    debug_data
+        .get_addr_for_function(file, name)
+        .ok_or_else(|| format!("Could not find address for correct usage {}", location))
+}
+
+/// Finds where to put a breakpoint on `location`, and the function and line there, in the
+/// executable or one of `libraries`. `<library>:<location>` looks only in the named library.
+fn resolve_breakpoint(
+    debug_data: &DwarfData,
+    libraries: &[SharedLibrary],
+    location: &str,
+) -> Result<(usize, Option<String>, Option<Line>), String> {
+    let describe = |debug_data: &DwarfData, addr: usize| {
+        (
+            addr,
+            debug_data.get_function_from_addr(addr),
+            debug_data.get_line_from_addr(addr),
+        )
+    };
+    if let Some((name, rest)) = location.split_once(':') {
+        if names_library(name) {
+            let library = libraries
+                .iter()
+                .find(|library| library.is_named(name))
+                .ok_or_else(|| format!("No shared library \"{}\" is loaded.", name))?;
+            let lib_data = library
+                .debug_data
+                .as_ref()
+                .ok_or_else(|| format!("No debugging symbols in {}.", library.path))?;
+            let addr = resolve_location(lib_data, rest)?;
+            return Ok(describe(lib_data, addr));
+        }
+    }
+    let err = match resolve_location(debug_data, location) {
+        Ok(addr) => return Ok(describe(debug_data, addr)),
+        Err(err) => err,
+    };
+    // Not in the executable, but perhaps in a library
+    for library in libraries.iter() {
+        if let Some(lib_data) = &library.debug_data {
+            if let Ok(addr) = resolve_location(lib_data, location) {
+                return Ok(describe(lib_data, addr));
+            }
+        }
+    }
+    Err(err)
+}
+
+/// Returns true if the part of a location before the colon names a shared library rather than a
+/// source file, e.g. "libfoo.so" or "/usr/lib/libfoo.so.1".
+fn names_library(name: &str) -> bool {
+    name.ends_with(".so") || name.contains(".so.")
+}
+
+/// Finds the one source file the user means by `file`.
+fn resolve_file<'a>(debug_data: &'a DwarfData, file: &str) -> Result<&'a str, String> {
+    match debug_data.find_files(file).as_slice() {
+        [] => Err(format!("No source file named {}.", file)),
+        [name] => Ok(*name),
+        names => Err(format!(
+            "Source file name \"{}\" is ambiguous: {}",
+            file,
+            names.join(", ")
+        )),
+    }
+}
+
+/// Size of the inferior's memory pages, which reads are kept within when the extent of a
+/// mapping is unknown.
+const PAGE_SIZE: usize = 4096;
+
+/// Number of lines `list` shows at a time.
+const LIST_SIZE: usize = 10;
+
+/// Returns the first line to list so that `line` is in the middle.
+fn list_start(line: usize) -> usize {
+    line.saturating_sub(LIST_SIZE / 2).max(1)
+}
+
+/// Returns true if two paths name the same source file, allowing for one of them to leave out
+/// leading directories.
+fn same_file(a: &str, b: &str) -> bool {
+    Path::new(a).ends_with(b) || Path::new(b).ends_with(a)
+}
+
+/// Splits "<lhs> = <rhs>" into its trimmed sides.
+fn split_assignment(assignment: &str) -> Option<(&str, &str)> {
+    let index = assignment.find('=')?;
+    Some((assignment[..index].trim(), assignment[index + 1..].trim()))
+}
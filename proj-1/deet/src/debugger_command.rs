@@ -1,11 +1,38 @@
 pub enum DebuggerCommand {
     Quit,
     Run(Vec<String>),
+    Attach(i32),
+    Detach,
     Continue,
     Backtrace,
-    BreakPoint(String),
+    /// Breakpoint location, and the source of an optional `if` condition.
+    BreakPoint(String, Option<String>),
+    Delete(Option<usize>),
+    Enable(usize),
+    Disable(usize),
+    Ignore(usize, usize),
+    /// What to show, and any arguments, e.g. the registers for `info registers`.
+    Info(String, Vec<String>),
+    Thread(Option<usize>),
     Next,
-    Print,
+    Step,
+    StepInstruction,
+    NextInstruction,
+    Finish,
+    Print(String),
+    /// Line, function or file:line to list around, or None to carry on from the last listing.
+    List(Option<String>),
+    Directory(Vec<String>),
+    /// Function to disassemble, or None for the one the inferior is stopped in.
+    Disassemble(Option<String>),
+    Watch(String),
+    /// The `NFU` part of `x/NFU`, and the address or variable to examine.
+    Examine(String, String),
+    /// Where to write the core file, or None for core.<pid>.
+    GenerateCore(Option<String>),
+    Set(String),
+    /// Signal name, and the keywords saying what to do with it.
+    Handle(String, Vec<String>),
     Help,
     Exit,
 }
@@ -20,11 +47,65 @@ impl DebuggerCommand {
                     args.iter().map(|s| s.to_string()).collect(),
                 ))
             }
+            "attach" => Some(DebuggerCommand::Attach(tokens.get(1)?.parse().ok()?)),
+            "detach" => Some(DebuggerCommand::Detach),
             "c" | "cont" | "continue" => Some(DebuggerCommand::Continue),
             "bt" | "back" | "backtrace" => Some(DebuggerCommand::Backtrace),
-            "b" | "break" => Some(DebuggerCommand::BreakPoint(tokens[1].to_string())),
+            "b" | "break" => {
+                let condition = match tokens.get(2) {
+                    Some(&"if") => Some(tokens[3..].join(" ")),
+                    Some(_) => return None,
+                    None => None,
+                };
+                Some(DebuggerCommand::BreakPoint(
+                    tokens.get(1)?.to_string(),
+                    condition,
+                ))
+            }
+            "d" | "delete" => match tokens.get(1) {
+                Some(id) => Some(DebuggerCommand::Delete(Some(id.parse().ok()?))),
+                None => Some(DebuggerCommand::Delete(None)),
+            },
+            "enable" => Some(DebuggerCommand::Enable(tokens.get(1)?.parse().ok()?)),
+            "dis" | "disable" => Some(DebuggerCommand::Disable(tokens.get(1)?.parse().ok()?)),
+            "ignore" => Some(DebuggerCommand::Ignore(
+                tokens.get(1)?.parse().ok()?,
+                tokens.get(2)?.parse().ok()?,
+            )),
+            "i" | "info" => Some(DebuggerCommand::Info(
+                tokens.get(1)?.to_string(),
+                tokens[2..].iter().map(|s| s.to_string()).collect(),
+            )),
+            "t" | "thread" => match tokens.get(1) {
+                Some(id) => Some(DebuggerCommand::Thread(Some(id.parse().ok()?))),
+                None => Some(DebuggerCommand::Thread(None)),
+            },
             "n" | "next" => Some(DebuggerCommand::Next),
-            "p" | "print" => Some(DebuggerCommand::Print),
+            "s" | "step" => Some(DebuggerCommand::Step),
+            "si" | "stepi" => Some(DebuggerCommand::StepInstruction),
+            "ni" | "nexti" => Some(DebuggerCommand::NextInstruction),
+            "fin" | "finish" => Some(DebuggerCommand::Finish),
+            "p" | "print" => Some(DebuggerCommand::Print(tokens[1..].join(" "))),
+            "disas" | "disassemble" => Some(DebuggerCommand::Disassemble(
+                tokens.get(1).map(|s| s.to_string()),
+            )),
+            "l" | "list" => Some(DebuggerCommand::List(tokens.get(1).map(|s| s.to_string()))),
+            "dir" | "directory" => Some(DebuggerCommand::Directory(
+                tokens[1..].iter().map(|s| s.to_string()).collect(),
+            )),
+            "watch" => Some(DebuggerCommand::Watch(tokens[1..].join(" "))),
+            cmd if cmd == "x" || cmd.starts_with("x/") => Some(DebuggerCommand::Examine(
+                cmd[1..].trim_start_matches('/').to_string(),
+                tokens[1..].join(" "),
+            )),
+            "gcore" | "generate-core-file" => Some(DebuggerCommand::GenerateCore(
+                tokens.get(1).map(|s| s.to_string()),
+            )),
+            "set" => Some(DebuggerCommand::Set(tokens[1..].join(" "))),
+            "handle" => Some(DebuggerCommand::Handle(
+                tokens.get(1)?.to_string(),
+                tokens[2..].iter().map(|s| s.to_string()).collect(),
+            )),
             "h" | "help" => Some(DebuggerCommand::Help),
             "e" | "exit" => Some(DebuggerCommand::Exit),
             _ => None,
@@ -0,0 +1,36 @@
+//! Decoding of x86-64 machine code into AT&T syntax, as used by `disassemble` and `x/i`.
+
+use iced_x86::{Decoder, DecoderError, DecoderOptions, Formatter, GasFormatter, Instruction};
+
+/// The longest an x86-64 instruction can be.
+pub const MAX_INSTRUCTION_LEN: usize = 15;
+
+pub struct DecodedInstruction {
+    pub addr: usize,
+    /// Length of the instruction in bytes.
+    pub len: usize,
+    pub text: String,
+}
+
+/// Decodes `bytes`, which were read from `addr`, stopping after `limit` instructions if given.
+/// An instruction cut off by the end of `bytes` is left out.
+pub fn decode(bytes: &[u8], addr: usize, limit: Option<usize>) -> Vec<DecodedInstruction> {
+    let mut decoder = Decoder::with_ip(64, bytes, addr as u64, DecoderOptions::NONE);
+    let mut formatter = GasFormatter::new();
+    let mut instruction = Instruction::default();
+    let mut decoded = Vec::new();
+    while decoder.can_decode() && limit.map_or(true, |limit| decoded.len() < limit) {
+        decoder.decode_out(&mut instruction);
+        if decoder.last_error() == DecoderError::NoMoreBytes {
+            break;
+        }
+        let mut text = String::new();
+        formatter.format(&instruction, &mut text);
+        decoded.push(DecodedInstruction {
+            addr: instruction.ip() as usize,
+            len: instruction.len(),
+            text,
+        });
+    }
+    decoded
+}
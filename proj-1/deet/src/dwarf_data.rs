@@ -1,7 +1,9 @@
 use crate::gimli_wrapper;
 use addr2line::Context;
 use object::Object;
+use std::collections::HashMap;
 use std::convert::TryInto;
+use std::path::Path;
 use std::{fmt, fs};
 
 #[derive(Debug)]
@@ -12,7 +14,13 @@ pub enum Error {
 
 pub struct DwarfData {
     files: Vec<File>,
+    types: HashMap<usize, Type>,
     addr2line: Context<addr2line::gimli::EndianRcSlice<addr2line::gimli::RunTimeEndian>>,
+    /// Entry point the object was linked with.
+    entry: usize,
+    /// Difference between where the object was loaded and the addresses it was linked at. Every
+    /// address in the structures we hand out has it added.
+    load_bias: usize,
 }
 
 impl fmt::Debug for DwarfData {
@@ -38,17 +46,84 @@ impl DwarfData {
         } else {
             gimli::RunTimeEndian::Big
         };
+        let (files, types) = gimli_wrapper::load_file(&object, endian)?;
         Ok(DwarfData {
-            files: gimli_wrapper::load_file(&object, endian)?,
+            files,
+            types,
             addr2line: Context::new(&object).or_else(|e| Err(gimli_wrapper::Error::from(e)))?,
+            entry: object.entry() as usize,
+            load_bias: 0,
         })
     }
 
+    /// Returns the link-time entry point, which the runtime one can be compared with to find
+    /// the load bias.
+    pub fn entry_point(&self) -> usize {
+        self.entry
+    }
+
+    pub fn load_bias(&self) -> usize {
+        self.load_bias
+    }
+
+    /// Moves every address to where the object was loaded, `bias` bytes from where it was
+    /// linked. Only position-independent executables and shared libraries need this.
+    pub fn set_load_bias(&mut self, bias: usize) {
+        let delta = bias.wrapping_sub(self.load_bias);
+        self.load_bias = bias;
+        for file in self.files.iter_mut() {
+            for var in file.global_variables.iter_mut() {
+                var.location.relocate(delta);
+            }
+            for func in file.functions.iter_mut() {
+                func.address = func.address.wrapping_add(delta);
+                for var in func.variables.iter_mut() {
+                    var.location.relocate(delta);
+                }
+                if let Some(frame_base) = &mut func.frame_base {
+                    frame_base.relocate(delta);
+                }
+            }
+            for line in file.lines.iter_mut() {
+                line.address = line.address.wrapping_add(delta);
+            }
+        }
+    }
+
     #[allow(dead_code)]
     fn get_target_file(&self, file: &str) -> Option<&File> {
-        self.files.iter().find(|f| {
-            f.name == file || (!file.contains("/") && f.name.ends_with(&format!("/{}", file)))
-        })
+        self.get_target_files(file).into_iter().next()
+    }
+
+    /// Returns the units whose source file is `file`. A name without a directory matches a
+    /// file of that name in any directory, unless some unit has exactly that name.
+    fn get_target_files(&self, file: &str) -> Vec<&File> {
+        let exact: Vec<&File> = self.files.iter().filter(|f| f.name == file).collect();
+        if !exact.is_empty() || file.contains('/') {
+            return exact;
+        }
+        self.files
+            .iter()
+            .filter(|f| f.name.ends_with(&format!("/{}", file)))
+            .collect()
+    }
+
+    /// Returns the names of the source files matching `file`, as for `get_target_file`. More
+    /// than one means the name is ambiguous.
+    pub fn find_files(&self, file: &str) -> Vec<&str> {
+        self.get_target_files(file)
+            .into_iter()
+            .map(|f| f.name.as_str())
+            .collect()
+    }
+
+    /// Returns the names of the source files that define a function called `func_name`.
+    pub fn find_function_files(&self, func_name: &str) -> Vec<&str> {
+        self.files
+            .iter()
+            .filter(|file| file.functions.iter().any(|func| func.name == func_name))
+            .map(|file| file.name.as_str())
+            .collect()
     }
 
     #[allow(dead_code)]
@@ -87,11 +162,67 @@ impl DwarfData {
         }
     }
 
+    /// Returns the source file and line where function `func_name` is declared.
+    pub fn get_function_source(&self, func_name: &str) -> Option<(String, usize)> {
+        self.files.iter().find_map(|file| {
+            let func = file.functions.iter().find(|func| func.name == func_name)?;
+            Some((file.name.clone(), func.line_number))
+        })
+    }
+
+    /// Returns the compilation directory of the unit for source file `file`, which may be
+    /// named as in the unit or already joined onto the compilation directory.
+    pub fn get_comp_dir(&self, file: &str) -> Option<&str> {
+        self.files
+            .iter()
+            .find(|f| match &f.comp_dir {
+                Some(comp_dir) => {
+                    f.name == file || Path::new(comp_dir).join(&f.name) == Path::new(file)
+                }
+                None => false,
+            })
+            .and_then(|f| f.comp_dir.as_deref())
+    }
+
+    /// Returns the name of the first compilation unit's source file.
+    pub fn get_main_file(&self) -> Option<&str> {
+        self.files.first().map(|file| file.name.as_str())
+    }
+
+    #[allow(dead_code)]
+    pub fn get_function_containing(&self, curr_addr: usize) -> Option<&Function> {
+        self.files
+            .iter()
+            .flat_map(|file| file.functions.iter())
+            .find(|func| func.address <= curr_addr && curr_addr < func.address + func.text_length)
+    }
+
+    #[allow(dead_code)]
+    pub fn get_global_variable(&self, name: &str) -> Option<&Variable> {
+        self.files
+            .iter()
+            .flat_map(|file| file.global_variables.iter())
+            .find(|var| var.name == name)
+    }
+
+    /// Returns the address of the first line after the function's prologue, which is where
+    /// stepping into the function should stop.
+    #[allow(dead_code)]
+    pub fn get_function_body_addr(&self, func: &Function) -> Option<usize> {
+        self.files
+            .iter()
+            .flat_map(|file| file.lines.iter())
+            .map(|line| line.address)
+            .filter(|addr| func.address < *addr && *addr < func.address + func.text_length)
+            .min()
+    }
+
     #[allow(dead_code)]
     pub fn get_line_from_addr(&self, curr_addr: usize) -> Option<Line> {
+        let link_addr = curr_addr.checked_sub(self.load_bias)?;
         let location = self
             .addr2line
-            .find_location(curr_addr.try_into().unwrap())
+            .find_location(link_addr.try_into().unwrap())
             .ok()??;
         Some(Line {
             file: location.file?.to_string(),
@@ -102,15 +233,196 @@ impl DwarfData {
 
     #[allow(dead_code)]
     pub fn get_function_from_addr(&self, curr_addr: usize) -> Option<String> {
+        let link_addr = curr_addr.checked_sub(self.load_bias)?;
         let frame = self
             .addr2line
-            .find_frames(curr_addr.try_into().unwrap())
+            .find_frames(link_addr.try_into().unwrap())
             .ok()?
             .next()
             .ok()??;
         Some(frame.function?.raw_name().ok()?.to_string())
     }
 
+    /// Formats a value of type `ty` from its in-memory representation, recursing into the
+    /// members of structs and the elements of arrays.
+    #[allow(dead_code)]
+    pub fn format_value(&self, ty: &Type, bytes: &[u8]) -> String {
+        match &ty.kind {
+            TypeKind::Base | TypeKind::Pointer(_) => ty.format_value(bytes_to_u64(bytes)),
+            TypeKind::Typedef(target) => match target.and_then(|offset| self.types.get(&offset)) {
+                Some(target) => self.format_value(target, bytes),
+                None => ty.format_value(bytes_to_u64(bytes)),
+            },
+            TypeKind::Enum(enumerators) => {
+                let raw = bytes_to_u64(bytes);
+                let mask = if ty.size >= 8 {
+                    u64::MAX
+                } else {
+                    (1 << (8 * ty.size)) - 1
+                };
+                match enumerators
+                    .iter()
+                    .find(|(_, value)| *value as u64 & mask == raw & mask)
+                {
+                    Some((name, _)) => name.clone(),
+                    None => format!("{}", sign_extend(raw, ty.size)),
+                }
+            }
+            TypeKind::Struct(members) => {
+                let fields: Vec<String> = members
+                    .iter()
+                    .map(|member| {
+                        let value = match self.types.get(&member.type_offset) {
+                            Some(member_type) if member.offset < bytes.len() => {
+                                match member.bit_field {
+                                    Some((bit, width)) => self.format_bit_field(
+                                        member_type,
+                                        &bytes[member.offset..],
+                                        bit,
+                                        width,
+                                    ),
+                                    None => {
+                                        let end =
+                                            (member.offset + member_type.size).min(bytes.len());
+                                        self.format_value(member_type, &bytes[member.offset..end])
+                                    }
+                                }
+                            }
+                            _ => "<unavailable>".to_string(),
+                        };
+                        format!("{} = {}", member.name, value)
+                    })
+                    .collect();
+                format!("{{{}}}", fields.join(", "))
+            }
+            TypeKind::Array {
+                element,
+                dimensions,
+            } => {
+                let element_type = match self.types.get(element) {
+                    Some(element_type) => element_type,
+                    None => return "{...}".to_string(),
+                };
+                // Multi-dimensional arrays are arrays of arrays
+                let inner = if dimensions.len() > 1 {
+                    Type::with_kind(
+                        String::new(),
+                        element_type.size * dimensions[1..].iter().product::<usize>(),
+                        TypeKind::Array {
+                            element: *element,
+                            dimensions: dimensions[1..].to_vec(),
+                        },
+                    )
+                } else {
+                    element_type.clone()
+                };
+                if inner.size == 0 {
+                    return "{...}".to_string();
+                }
+                if inner.size == 1 && inner.name.contains("char") {
+                    let len = bytes.iter().position(|b| *b == 0).unwrap_or(bytes.len());
+                    return format!("{:?}", String::from_utf8_lossy(&bytes[..len]));
+                }
+                let elements: Vec<String> = bytes
+                    .chunks(inner.size)
+                    .map(|chunk| self.format_value(&inner, chunk))
+                    .collect();
+                format!("{{{}}}", elements.join(", "))
+            }
+        }
+    }
+
+    /// Formats a bitfield of type `ty`, `width` bits wide and starting `bit` bits into `bytes`.
+    fn format_bit_field(&self, ty: &Type, bytes: &[u8], bit: usize, width: usize) -> String {
+        if width == 0 || bit + width > 64 || ty.size > 8 {
+            return "<unsupported bitfield>".to_string();
+        }
+        let mut raw = bytes_to_u64(bytes) >> bit;
+        if width < 64 {
+            raw &= (1 << width) - 1;
+            // Fill in the bits above the field with its sign
+            if self.is_signed(ty) && raw & (1 << (width - 1)) != 0 {
+                raw |= !0 << width;
+            }
+        }
+        self.format_value(ty, &raw.to_le_bytes()[..ty.size])
+    }
+
+    /// Returns true if values of scalar type `ty` can be negative.
+    fn is_signed(&self, ty: &Type) -> bool {
+        match &ty.kind {
+            TypeKind::Typedef(target) => target
+                .and_then(|offset| self.types.get(&offset))
+                .map_or(false, |target| self.is_signed(target)),
+            TypeKind::Base => !ty.name.contains("unsigned") && ty.name != "_Bool",
+            TypeKind::Enum(_) => true,
+            _ => false,
+        }
+    }
+
+    /// Returns true if a value of type `ty` holds any floating-point numbers, which the x86-64
+    /// calling convention passes in SSE registers rather than general-purpose ones.
+    pub fn contains_floating_point(&self, ty: &Type) -> bool {
+        let contains = |offset: &usize| {
+            self.types
+                .get(offset)
+                .map_or(false, |ty| self.contains_floating_point(ty))
+        };
+        match &ty.kind {
+            TypeKind::Base => ty.name.contains("float") || ty.name.contains("double"),
+            TypeKind::Typedef(target) => target.as_ref().map_or(false, contains),
+            TypeKind::Struct(members) => members.iter().any(|member| contains(&member.type_offset)),
+            TypeKind::Array { element, .. } => contains(element),
+            TypeKind::Pointer(_) | TypeKind::Enum(_) => false,
+        }
+    }
+
+    /// Encodes an integer as a value of scalar type `ty`, the reverse of `value_as_i64`. Returns
+    /// None for aggregates.
+    pub fn encode_value(&self, ty: &Type, value: i64) -> Option<Vec<u8>> {
+        let bytes = match &ty.kind {
+            TypeKind::Typedef(target) => {
+                return self.encode_value(self.types.get(&(*target)?)?, value)
+            }
+            TypeKind::Base if ty.name == "float" => (value as f32).to_le_bytes().to_vec(),
+            TypeKind::Base if ty.name == "double" => (value as f64).to_le_bytes().to_vec(),
+            TypeKind::Base | TypeKind::Pointer(_) | TypeKind::Enum(_) => {
+                value.to_le_bytes()[..ty.size.min(8)].to_vec()
+            }
+            TypeKind::Struct(_) | TypeKind::Array { .. } => return None,
+        };
+        Some(bytes)
+    }
+
+    /// Encodes a number as a value of type `ty` if it is a float or double. Returns None for
+    /// every other type.
+    pub fn encode_float(&self, ty: &Type, value: f64) -> Option<Vec<u8>> {
+        match &ty.kind {
+            TypeKind::Typedef(target) => self.encode_float(self.types.get(&(*target)?)?, value),
+            TypeKind::Base if ty.name == "float" => Some((value as f32).to_le_bytes().to_vec()),
+            TypeKind::Base if ty.name == "double" => Some(value.to_le_bytes().to_vec()),
+            _ => None,
+        }
+    }
+
+    /// Interprets a scalar value (integer, character, enum or pointer) as an integer, as needed
+    /// for arithmetic and comparisons. Returns None for aggregates.
+    pub fn value_as_i64(&self, ty: &Type, bytes: &[u8]) -> Option<i64> {
+        let raw = bytes_to_u64(bytes);
+        match &ty.kind {
+            TypeKind::Typedef(target) => self.value_as_i64(self.types.get(&(*target)?)?, bytes),
+            TypeKind::Pointer(_) => Some(raw as i64),
+            TypeKind::Enum(_) => Some(sign_extend(raw, ty.size)),
+            TypeKind::Base if ty.name == "float" => Some(f32::from_bits(raw as u32) as i64),
+            TypeKind::Base if ty.name == "double" => Some(f64::from_bits(raw) as i64),
+            TypeKind::Base if ty.name.contains("unsigned") || ty.name == "_Bool" => {
+                Some(raw as i64)
+            }
+            TypeKind::Base => Some(sign_extend(raw, ty.size)),
+            TypeKind::Struct(_) | TypeKind::Array { .. } => None,
+        }
+    }
+
     #[allow(dead_code)]
     pub fn print(&self) {
         for file in &self.files {
@@ -152,25 +464,135 @@ impl DwarfData {
 pub struct Type {
     pub name: String,
     pub size: usize,
+    pub kind: TypeKind,
+}
+
+/// The shape of a type. Types that refer to other types do so by their offset in .debug_info,
+/// which can be looked up in `DwarfData`.
+#[derive(Debug, Clone)]
+pub enum TypeKind {
+    Base,
+    Pointer(Option<usize>), // None for void *
+    Typedef(Option<usize>),
+    Struct(Vec<Member>),
+    Enum(Vec<(String, i64)>),
+    Array {
+        element: usize,
+        dimensions: Vec<usize>,
+    },
+}
+
+impl Default for TypeKind {
+    fn default() -> Self {
+        TypeKind::Base
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Member {
+    pub name: String,
+    pub offset: usize, // Byte offset within the enclosing struct
+    pub type_offset: usize,
+    /// For a bitfield, its offset in bits from `offset`, counting from the least significant
+    /// bit, and its width in bits.
+    pub bit_field: Option<(usize, usize)>,
 }
 
 impl Type {
     pub fn new(name: String, size: usize) -> Self {
-        Type { name, size }
+        Type::with_kind(name, size, TypeKind::Base)
+    }
+
+    pub fn with_kind(name: String, size: usize, kind: TypeKind) -> Self {
+        Type { name, size, kind }
+    }
+
+    /// Formats a raw value of this type, as read from memory or a register.
+    pub fn format_value(&self, raw: u64) -> String {
+        if self.size == 0 || self.size > 8 {
+            return format!("{:#x}", raw);
+        }
+        let bits = 8 * self.size as u32;
+        let raw = if bits < 64 {
+            raw & ((1 << bits) - 1)
+        } else {
+            raw
+        };
+        if let TypeKind::Pointer(_) = self.kind {
+            format!("{:#x}", raw)
+        } else if self.name == "float" {
+            format!("{}", f32::from_bits(raw as u32))
+        } else if self.name == "double" {
+            format!("{}", f64::from_bits(raw))
+        } else if self.name.contains("char") && self.size == 1 {
+            format!("{} {:?}", raw as u8 as i8, raw as u8 as char)
+        } else if self.name.contains("unsigned") || self.name == "_Bool" {
+            format!("{}", raw)
+        } else {
+            format!("{}", sign_extend(raw, self.size))
+        }
+    }
+}
+
+/// Interprets up to 8 little-endian bytes as an integer.
+fn bytes_to_u64(bytes: &[u8]) -> u64 {
+    let mut raw = [0u8; 8];
+    let len = bytes.len().min(8);
+    raw[..len].copy_from_slice(&bytes[..len]);
+    u64::from_le_bytes(raw)
+}
+
+/// Sign-extends the low `size` bytes of `raw` to 64 bits.
+fn sign_extend(raw: u64, size: usize) -> i64 {
+    if size == 0 || size >= 8 {
+        return raw as i64;
     }
+    let shift = 64 - 8 * size as u32;
+    ((raw << shift) as i64) >> shift
 }
 
 #[derive(Clone)]
 pub enum Location {
     Address(usize),
     FramePointerOffset(isize),
+    /// Any other DWARF expression, evaluated by `dwarf_expr` when the variable is read. The
+    /// encoding is that of the unit it came from, which determines the size of some operands.
+    Expression(Vec<u8>, gimli::Encoding),
+    /// A location list: the variable lives in different places depending on the pc.
+    List(Vec<LocationRange>, gimli::Encoding),
+}
+
+#[derive(Debug, Clone)]
+pub struct LocationRange {
+    pub begin: usize,
+    pub end: usize,
+    pub expression: Vec<u8>,
+}
+
+impl Location {
+    /// Moves the addresses in the location `delta` bytes, wrapping for negative moves.
+    fn relocate(&mut self, delta: usize) {
+        match self {
+            Location::Address(addr) => *addr = addr.wrapping_add(delta),
+            Location::List(ranges, _) => {
+                for range in ranges.iter_mut() {
+                    range.begin = range.begin.wrapping_add(delta);
+                    range.end = range.end.wrapping_add(delta);
+                }
+            }
+            // DW_OP_addr operands are relocated as the expression is evaluated
+            Location::FramePointerOffset(_) | Location::Expression(..) => {}
+        }
+    }
 }
 
 impl fmt::Display for Location {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match *self {
+        match self {
             Location::Address(addr) => write!(f, "Address({:#x})", addr),
             Location::FramePointerOffset(offset) => write!(f, "FramePointerOffset({})", offset),
+            Location::Expression(expression, _) => write!(f, "Expression({:02x?})", expression),
+            Location::List(ranges, _) => write!(f, "List({} ranges)", ranges.len()),
         }
     }
 }
@@ -197,11 +619,15 @@ pub struct Function {
     pub text_length: usize,
     pub line_number: usize, // Line number in source file
     pub variables: Vec<Variable>,
+    pub return_type: Option<Type>,
+    pub frame_base: Option<Location>,
 }
 
 #[derive(Debug, Default, Clone)]
 pub struct File {
     pub name: String,
+    /// Directory the compiler ran in, which relative paths in the unit are relative to.
+    pub comp_dir: Option<String>,
     pub global_variables: Vec<Variable>,
     pub functions: Vec<Function>,
     pub lines: Vec<Line>,
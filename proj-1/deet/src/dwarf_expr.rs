@@ -0,0 +1,674 @@
+//! A small evaluator for DWARF location expressions. gimli parses the individual operations; this
+//! module runs them against the registers and memory of a stopped inferior.
+
+use crate::dwarf_data::{Location, LocationRange};
+use std::fmt;
+
+/// Everything an expression may ask about the frame it is evaluated in.
+pub struct EvalContext<'a> {
+    pub regs: &'a libc::user_regs_struct,
+    /// Canonical frame address of the frame, if known.
+    pub cfa: Option<usize>,
+    /// Value of the enclosing function's DW_AT_frame_base, if known.
+    pub frame_base: Option<usize>,
+    pub read_memory: &'a dyn Fn(usize, usize) -> Result<Vec<u8>, nix::Error>,
+    /// Added to DW_OP_addr operands, which are link-time addresses.
+    pub load_bias: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PieceLocation {
+    /// The piece lives in memory at this address.
+    Memory(usize),
+    /// The piece lives in a register (DWARF numbering).
+    Register(u16),
+    /// The piece has no location, but its value is known (DW_OP_stack_value).
+    Value(u64),
+    /// The piece has no location, but its bytes are known (DW_OP_implicit_value).
+    Bytes(Vec<u8>),
+    /// The piece has been optimized out.
+    Empty,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Piece {
+    pub location: PieceLocation,
+    /// Size of the piece in bytes, or None if it covers the whole object.
+    pub size: Option<usize>,
+}
+
+#[derive(Debug)]
+pub enum EvalError {
+    OptimizedOut,
+    Unsupported(String),
+    MissingRegister(u16),
+    MissingFrameBase,
+    MissingCfa,
+    StackUnderflow,
+    Malformed,
+    Memory(nix::Error),
+}
+
+impl fmt::Display for EvalError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EvalError::OptimizedOut => write!(f, "<optimized out>"),
+            EvalError::Unsupported(op) => write!(f, "unsupported DWARF operation {}", op),
+            EvalError::MissingRegister(reg) => write!(f, "unknown DWARF register {}", reg),
+            EvalError::MissingFrameBase => write!(f, "frame base is not available"),
+            EvalError::MissingCfa => write!(f, "canonical frame address is not available"),
+            EvalError::StackUnderflow => write!(f, "DWARF expression stack underflow"),
+            EvalError::Malformed => write!(f, "malformed DWARF expression"),
+            EvalError::Memory(err) => write!(f, "could not read memory: {}", err),
+        }
+    }
+}
+
+impl From<nix::Error> for EvalError {
+    fn from(err: nix::Error) -> Self {
+        EvalError::Memory(err)
+    }
+}
+
+/// Returns the value of a register, using the DWARF register numbering for x86-64.
+pub fn dwarf_register(regs: &libc::user_regs_struct, register: u16) -> Option<u64> {
+    Some(match register {
+        0 => regs.rax,
+        1 => regs.rdx,
+        2 => regs.rcx,
+        3 => regs.rbx,
+        4 => regs.rsi,
+        5 => regs.rdi,
+        6 => regs.rbp,
+        7 => regs.rsp,
+        8 => regs.r8,
+        9 => regs.r9,
+        10 => regs.r10,
+        11 => regs.r11,
+        12 => regs.r12,
+        13 => regs.r13,
+        14 => regs.r14,
+        15 => regs.r15,
+        16 => regs.rip, // The return address column
+        _ => return None,
+    })
+}
+
+/// Sets the value of a register, using the DWARF register numbering for x86-64. Returns false if
+/// the register isn't one we track.
+pub fn set_dwarf_register(regs: &mut libc::user_regs_struct, register: u16, value: u64) -> bool {
+    let slot = match register {
+        0 => &mut regs.rax,
+        1 => &mut regs.rdx,
+        2 => &mut regs.rcx,
+        3 => &mut regs.rbx,
+        4 => &mut regs.rsi,
+        5 => &mut regs.rdi,
+        6 => &mut regs.rbp,
+        7 => &mut regs.rsp,
+        8 => &mut regs.r8,
+        9 => &mut regs.r9,
+        10 => &mut regs.r10,
+        11 => &mut regs.r11,
+        12 => &mut regs.r12,
+        13 => &mut regs.r13,
+        14 => &mut regs.r14,
+        15 => &mut regs.r15,
+        16 => &mut regs.rip,
+        _ => return false,
+    };
+    *slot = value;
+    true
+}
+
+fn pop(stack: &mut Vec<u64>) -> Result<u64, EvalError> {
+    stack.pop().ok_or(EvalError::StackUnderflow)
+}
+
+/// Evaluates a DWARF expression and returns the pieces that make up the object it describes.
+/// `encoding` is that of the unit or CIE the expression came from.
+pub fn evaluate(
+    expression: &[u8],
+    encoding: gimli::Encoding,
+    ctx: &EvalContext,
+) -> Result<Vec<Piece>, EvalError> {
+    evaluate_with_stack(expression, encoding, ctx, Vec::new())
+}
+
+/// Like `evaluate`, but starts with values already pushed onto the stack. CFI register rules,
+/// for example, start with the CFA on the stack.
+pub fn evaluate_with_stack(
+    expression: &[u8],
+    encoding: gimli::Encoding,
+    ctx: &EvalContext,
+    initial_stack: Vec<u64>,
+) -> Result<Vec<Piece>, EvalError> {
+    let mut stack: Vec<u64> = initial_stack;
+    let mut pieces: Vec<Piece> = Vec::new();
+    // Set by operations that describe where the current piece is, rather than its address
+    let mut current: Option<PieceLocation> = None;
+    let mut pc = 0;
+
+    while pc < expression.len() {
+        let mut reader = gimli::EndianSlice::new(&expression[pc..], gimli::LittleEndian);
+        let op = gimli::Operation::parse(&mut reader, encoding).map_err(|err| match err {
+            gimli::Error::InvalidExpression(op) => EvalError::Unsupported(op.to_string()),
+            _ => EvalError::Malformed,
+        })?;
+        pc = expression.len() - reader.len();
+
+        match op {
+            gimli::Operation::Address { address } => {
+                stack.push(address.wrapping_add(ctx.load_bias as u64))
+            }
+            gimli::Operation::UnsignedConstant { value } => stack.push(value),
+            gimli::Operation::SignedConstant { value } => stack.push(value as u64),
+            gimli::Operation::Register { register } => {
+                current = Some(PieceLocation::Register(register.0));
+            }
+            gimli::Operation::RegisterOffset {
+                register, offset, ..
+            } => {
+                let value = dwarf_register(ctx.regs, register.0)
+                    .ok_or(EvalError::MissingRegister(register.0))?;
+                stack.push(value.wrapping_add(offset as u64));
+            }
+            gimli::Operation::FrameOffset { offset } => {
+                let frame_base = ctx.frame_base.ok_or(EvalError::MissingFrameBase)?;
+                stack.push((frame_base as u64).wrapping_add(offset as u64));
+            }
+            gimli::Operation::CallFrameCFA => {
+                stack.push(ctx.cfa.ok_or(EvalError::MissingCfa)? as u64);
+            }
+            gimli::Operation::Deref { size, space, .. } => {
+                if space {
+                    pop(&mut stack)?;
+                }
+                let addr = pop(&mut stack)? as usize;
+                let bytes = (ctx.read_memory)(addr, size as usize)?;
+                let mut raw = [0u8; 8];
+                raw[..bytes.len().min(8)].copy_from_slice(&bytes[..bytes.len().min(8)]);
+                stack.push(u64::from_le_bytes(raw));
+            }
+            gimli::Operation::StackValue => {
+                current = Some(PieceLocation::Value(pop(&mut stack)?));
+            }
+            gimli::Operation::ImplicitValue { data } => {
+                current = Some(PieceLocation::Bytes(data.slice().to_vec()));
+            }
+            gimli::Operation::Piece {
+                size_in_bits,
+                bit_offset,
+            } => {
+                if bit_offset.is_some() || size_in_bits % 8 != 0 {
+                    return Err(EvalError::Unsupported("DW_OP_bit_piece".to_string()));
+                }
+                let location = match current.take() {
+                    Some(location) => location,
+                    None => match stack.pop() {
+                        Some(addr) => PieceLocation::Memory(addr as usize),
+                        None => PieceLocation::Empty,
+                    },
+                };
+                pieces.push(Piece {
+                    location,
+                    size: Some(size_in_bits as usize / 8),
+                });
+            }
+            gimli::Operation::PlusConstant { value } => {
+                let top = pop(&mut stack)?;
+                stack.push(top.wrapping_add(value));
+            }
+            gimli::Operation::Drop => {
+                pop(&mut stack)?;
+            }
+            gimli::Operation::Pick { index } => {
+                let index = stack
+                    .len()
+                    .checked_sub(index as usize + 1)
+                    .ok_or(EvalError::StackUnderflow)?;
+                stack.push(stack[index]);
+            }
+            gimli::Operation::Swap => {
+                let a = pop(&mut stack)?;
+                let b = pop(&mut stack)?;
+                stack.push(a);
+                stack.push(b);
+            }
+            gimli::Operation::Rot => {
+                let a = pop(&mut stack)?;
+                let b = pop(&mut stack)?;
+                let c = pop(&mut stack)?;
+                stack.push(a);
+                stack.push(c);
+                stack.push(b);
+            }
+            gimli::Operation::Abs => {
+                let a = pop(&mut stack)? as i64;
+                stack.push(a.wrapping_abs() as u64);
+            }
+            gimli::Operation::Neg => {
+                let a = pop(&mut stack)? as i64;
+                stack.push(a.wrapping_neg() as u64);
+            }
+            gimli::Operation::Not => {
+                let a = pop(&mut stack)?;
+                stack.push(!a);
+            }
+            gimli::Operation::And
+            | gimli::Operation::Or
+            | gimli::Operation::Xor
+            | gimli::Operation::Plus
+            | gimli::Operation::Minus
+            | gimli::Operation::Mul
+            | gimli::Operation::Div
+            | gimli::Operation::Mod
+            | gimli::Operation::Shl
+            | gimli::Operation::Shr
+            | gimli::Operation::Shra
+            | gimli::Operation::Eq
+            | gimli::Operation::Ne
+            | gimli::Operation::Lt
+            | gimli::Operation::Gt
+            | gimli::Operation::Le
+            | gimli::Operation::Ge => {
+                let b = pop(&mut stack)?;
+                let a = pop(&mut stack)?;
+                stack.push(binary_op(&op, a, b)?);
+            }
+            gimli::Operation::Skip { target } => {
+                pc = jump(pc, target, expression.len())?;
+            }
+            gimli::Operation::Bra { target } => {
+                if pop(&mut stack)? != 0 {
+                    pc = jump(pc, target, expression.len())?;
+                }
+            }
+            gimli::Operation::Nop => {}
+            // The value had on entry to the function, which optimized code describes when it
+            // no longer keeps the value anywhere. Recovering it needs the caller's call site
+            // information, which we don't read.
+            gimli::Operation::EntryValue { .. } => return Err(EvalError::OptimizedOut),
+            other => return Err(EvalError::Unsupported(format!("{:?}", other))),
+        }
+    }
+
+    // Anything left over describes the final (or only) piece
+    let location = match current {
+        Some(location) => Some(location),
+        None => stack.pop().map(|addr| PieceLocation::Memory(addr as usize)),
+    };
+    match location {
+        Some(location) => pieces.push(Piece {
+            location,
+            size: None,
+        }),
+        None if pieces.is_empty() => return Err(EvalError::OptimizedOut),
+        None => {}
+    }
+    Ok(pieces)
+}
+
+fn binary_op<R: gimli::Reader>(op: &gimli::Operation<R>, a: u64, b: u64) -> Result<u64, EvalError> {
+    Ok(match op {
+        gimli::Operation::And => a & b,
+        gimli::Operation::Or => a | b,
+        gimli::Operation::Xor => a ^ b,
+        gimli::Operation::Plus => a.wrapping_add(b),
+        gimli::Operation::Minus => a.wrapping_sub(b),
+        gimli::Operation::Mul => a.wrapping_mul(b),
+        gimli::Operation::Div => {
+            if b == 0 {
+                return Err(EvalError::Malformed);
+            }
+            (a as i64).wrapping_div(b as i64) as u64
+        }
+        gimli::Operation::Mod => {
+            if b == 0 {
+                return Err(EvalError::Malformed);
+            }
+            a % b
+        }
+        gimli::Operation::Shl => a.checked_shl(b as u32).unwrap_or(0),
+        gimli::Operation::Shr => a.checked_shr(b as u32).unwrap_or(0),
+        gimli::Operation::Shra => (a as i64).checked_shr(b as u32).unwrap_or(0) as u64,
+        gimli::Operation::Eq => (a == b) as u64,
+        gimli::Operation::Ne => (a != b) as u64,
+        gimli::Operation::Lt => ((a as i64) < (b as i64)) as u64,
+        gimli::Operation::Gt => ((a as i64) > (b as i64)) as u64,
+        gimli::Operation::Le => ((a as i64) <= (b as i64)) as u64,
+        gimli::Operation::Ge => ((a as i64) >= (b as i64)) as u64,
+        _ => return Err(EvalError::Malformed),
+    })
+}
+
+/// Applies a DW_OP_skip/DW_OP_bra offset, which is relative to the end of the branch.
+fn jump(pc: usize, target: i16, len: usize) -> Result<usize, EvalError> {
+    let target = pc as isize + target as isize;
+    if target < 0 || target as usize > len {
+        return Err(EvalError::Malformed);
+    }
+    Ok(target as usize)
+}
+
+/// Evaluates a variable's location at the given pc, resolving location lists to the entry that
+/// covers it.
+pub fn evaluate_location(
+    location: &Location,
+    pc: usize,
+    ctx: &EvalContext,
+) -> Result<Vec<Piece>, EvalError> {
+    let whole = |location: PieceLocation| -> Result<Vec<Piece>, EvalError> {
+        Ok(vec![Piece {
+            location,
+            size: None,
+        }])
+    };
+    match location {
+        Location::Address(addr) => whole(PieceLocation::Memory(*addr)),
+        Location::FramePointerOffset(offset) => {
+            let frame_base = ctx.frame_base.ok_or(EvalError::MissingFrameBase)?;
+            whole(PieceLocation::Memory(
+                (frame_base as isize + offset) as usize,
+            ))
+        }
+        Location::Expression(expression, encoding) => evaluate(expression, *encoding, ctx),
+        Location::List(ranges, encoding) => match ranges
+            .iter()
+            .find(|range: &&LocationRange| range.begin <= pc && pc < range.end)
+        {
+            Some(range) => evaluate(&range.expression, *encoding, ctx),
+            None => Err(EvalError::OptimizedOut),
+        },
+    }
+}
+
+/// Reads the bytes of an object of `size` bytes that is made up of `pieces`.
+pub fn read_pieces(pieces: &[Piece], size: usize, ctx: &EvalContext) -> Result<Vec<u8>, EvalError> {
+    let mut bytes = Vec::with_capacity(size);
+    for piece in pieces {
+        let len = piece.size.unwrap_or(size - bytes.len().min(size));
+        match &piece.location {
+            PieceLocation::Memory(addr) => bytes.extend((ctx.read_memory)(*addr, len)?),
+            PieceLocation::Register(register) => {
+                let value = dwarf_register(ctx.regs, *register)
+                    .ok_or(EvalError::MissingRegister(*register))?;
+                bytes.extend(value.to_le_bytes().iter().take(len));
+            }
+            PieceLocation::Value(value) => bytes.extend(value.to_le_bytes().iter().take(len)),
+            PieceLocation::Bytes(data) => bytes.extend(data.iter().take(len)),
+            PieceLocation::Empty => return Err(EvalError::OptimizedOut),
+        }
+    }
+    bytes.resize(size, 0);
+    Ok(bytes)
+}
+
+/// Evaluates a function's DW_AT_frame_base, which yields an address rather than an object.
+pub fn evaluate_frame_base(location: &Location, ctx: &EvalContext) -> Result<usize, EvalError> {
+    match evaluate_location(location, ctx.regs.rip as usize, ctx)?.first() {
+        Some(Piece {
+            location: PieceLocation::Memory(addr),
+            ..
+        }) => Ok(*addr),
+        Some(Piece {
+            location: PieceLocation::Register(register),
+            ..
+        }) => dwarf_register(ctx.regs, *register)
+            .map(|value| value as usize)
+            .ok_or(EvalError::MissingRegister(*register)),
+        _ => Err(EvalError::MissingFrameBase),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encoding() -> gimli::Encoding {
+        gimli::Encoding {
+            address_size: 8,
+            format: gimli::Format::Dwarf32,
+            version: 4,
+        }
+    }
+
+    /// Memory from 0x6000 to 0x8000, where each byte holds the low byte of its address.
+    fn read_memory(addr: usize, len: usize) -> Result<Vec<u8>, nix::Error> {
+        if addr < 0x6000 || addr + len > 0x8000 {
+            return Err(nix::Error::Sys(nix::errno::Errno::EFAULT));
+        }
+        Ok((addr..addr + len).map(|addr| addr as u8).collect())
+    }
+
+    fn with_context<T>(f: impl FnOnce(&EvalContext) -> T) -> T {
+        let mut regs: libc::user_regs_struct = unsafe { std::mem::zeroed() };
+        regs.rax = 42;
+        regs.rbp = 0x7000;
+        regs.rsp = 0x6ff0;
+        regs.rip = 0x1000;
+        f(&EvalContext {
+            regs: &regs,
+            cfa: Some(0x7010),
+            frame_base: Some(0x7000),
+            read_memory: &read_memory,
+            load_bias: 0x10000,
+        })
+    }
+
+    fn eval(expression: &[u8]) -> Result<Vec<Piece>, EvalError> {
+        with_context(|ctx| evaluate(expression, encoding(), ctx))
+    }
+
+    fn whole(location: PieceLocation) -> Vec<Piece> {
+        vec![Piece {
+            location,
+            size: None,
+        }]
+    }
+
+    fn piece(location: PieceLocation, size: usize) -> Piece {
+        Piece {
+            location,
+            size: Some(size),
+        }
+    }
+
+    #[test]
+    fn arithmetic() {
+        // (5 - 3) * 4
+        let expression = [0x35, 0x33, 0x1c, 0x34, 0x1e, 0x9f];
+        assert_eq!(eval(&expression).unwrap(), whole(PieceLocation::Value(8)));
+        // -7 / 2 rounds towards zero, and plus_uconst adds its operand
+        let expression = [0x09, 0xf9, 0x32, 0x1b, 0x23, 0x05, 0x9f];
+        assert_eq!(eval(&expression).unwrap(), whole(PieceLocation::Value(2)));
+        // 1 < 2 compares as signed, then -1 < 0 too
+        let expression = [0x31, 0x32, 0x2d, 0x9f];
+        assert_eq!(eval(&expression).unwrap(), whole(PieceLocation::Value(1)));
+        let expression = [0x09, 0xff, 0x30, 0x2d, 0x9f];
+        assert_eq!(eval(&expression).unwrap(), whole(PieceLocation::Value(1)));
+    }
+
+    #[test]
+    fn stack_operations() {
+        // rot moves the top entry to third place, leaving the old second on top
+        let expression = [0x31, 0x32, 0x33, 0x17, 0x9f];
+        assert_eq!(eval(&expression).unwrap(), whole(PieceLocation::Value(2)));
+        // swap, then 2 - 1
+        let expression = [0x31, 0x32, 0x16, 0x1c, 0x9f];
+        assert_eq!(eval(&expression).unwrap(), whole(PieceLocation::Value(1)));
+        // over copies the second entry
+        let expression = [0x37, 0x39, 0x14, 0x9f];
+        assert_eq!(eval(&expression).unwrap(), whole(PieceLocation::Value(7)));
+        // drop leaves the first entry
+        let expression = [0x37, 0x39, 0x13, 0x9f];
+        assert_eq!(eval(&expression).unwrap(), whole(PieceLocation::Value(7)));
+    }
+
+    #[test]
+    fn stack_underflow() {
+        assert!(matches!(
+            eval(&[0x31, 0x22]),
+            Err(EvalError::StackUnderflow)
+        ));
+        assert!(matches!(
+            eval(&[0x31, 0x15, 0x01]),
+            Err(EvalError::StackUnderflow)
+        ));
+    }
+
+    #[test]
+    fn branches() {
+        // cond; bra +4 (to lit6); lit5; skip +1 (to stack_value); lit6; stack_value
+        let expression = |cond| [cond, 0x28, 4, 0, 0x35, 0x2f, 1, 0, 0x36, 0x9f];
+        assert_eq!(
+            eval(&expression(0x30)).unwrap(),
+            whole(PieceLocation::Value(5))
+        );
+        assert_eq!(
+            eval(&expression(0x31)).unwrap(),
+            whole(PieceLocation::Value(6))
+        );
+        // Jumping past either end of the expression
+        assert!(matches!(eval(&[0x2f, 10, 0]), Err(EvalError::Malformed)));
+        assert!(matches!(
+            eval(&[0x2f, 0xf0, 0xff]),
+            Err(EvalError::Malformed)
+        ));
+    }
+
+    #[test]
+    fn addresses_and_registers() {
+        // breg6 -16
+        assert_eq!(
+            eval(&[0x76, 0x70]).unwrap(),
+            whole(PieceLocation::Memory(0x6ff0))
+        );
+        // fbreg 8
+        assert_eq!(
+            eval(&[0x91, 0x08]).unwrap(),
+            whole(PieceLocation::Memory(0x7008))
+        );
+        // call_frame_cfa
+        assert_eq!(eval(&[0x9c]).unwrap(), whole(PieceLocation::Memory(0x7010)));
+        // addr operands are moved by the load bias
+        let expression = [0x03, 0x00, 0x10, 0, 0, 0, 0, 0, 0];
+        assert_eq!(
+            eval(&expression).unwrap(),
+            whole(PieceLocation::Memory(0x11000))
+        );
+        // reg0
+        assert_eq!(eval(&[0x50]).unwrap(), whole(PieceLocation::Register(0)));
+    }
+
+    #[test]
+    fn dereferences() {
+        // breg6 0; deref
+        assert_eq!(
+            eval(&[0x76, 0x00, 0x06, 0x9f]).unwrap(),
+            whole(PieceLocation::Value(0x0706_0504_0302_0100))
+        );
+        // breg6 0; deref_size 2
+        assert_eq!(
+            eval(&[0x76, 0x00, 0x94, 0x02, 0x9f]).unwrap(),
+            whole(PieceLocation::Value(0x0100))
+        );
+        assert!(matches!(eval(&[0x30, 0x06]), Err(EvalError::Memory(_))));
+    }
+
+    #[test]
+    fn pieces() {
+        // reg0; piece 4; breg6 0; piece 4
+        assert_eq!(
+            eval(&[0x50, 0x93, 0x04, 0x76, 0x00, 0x93, 0x04]).unwrap(),
+            vec![
+                piece(PieceLocation::Register(0), 4),
+                piece(PieceLocation::Memory(0x7000), 4),
+            ]
+        );
+        // piece 4 with nothing before it is optimized out; lit1; stack_value; piece 2
+        assert_eq!(
+            eval(&[0x93, 0x04, 0x31, 0x9f, 0x93, 0x02]).unwrap(),
+            vec![
+                piece(PieceLocation::Empty, 4),
+                piece(PieceLocation::Value(1), 2),
+            ]
+        );
+        assert!(matches!(
+            eval(&[0x50, 0x9d, 0x04, 0x00]),
+            Err(EvalError::Unsupported(_))
+        ));
+    }
+
+    #[test]
+    fn implicit_values() {
+        assert_eq!(
+            eval(&[0x9e, 0x02, 0xab, 0xcd]).unwrap(),
+            whole(PieceLocation::Bytes(vec![0xab, 0xcd]))
+        );
+    }
+
+    #[test]
+    fn optimized_out() {
+        assert!(matches!(eval(&[]), Err(EvalError::OptimizedOut)));
+        // entry_value(reg0); stack_value
+        assert!(matches!(
+            eval(&[0xa3, 0x01, 0x50, 0x9f]),
+            Err(EvalError::OptimizedOut)
+        ));
+    }
+
+    #[test]
+    fn read_pieces_assembles_object() {
+        let pieces = [
+            piece(PieceLocation::Value(0x1122), 2),
+            piece(PieceLocation::Memory(0x7000), 2),
+            piece(PieceLocation::Register(0), 1),
+        ];
+        let bytes = with_context(|ctx| read_pieces(&pieces, 6, ctx)).unwrap();
+        assert_eq!(bytes, vec![0x22, 0x11, 0x00, 0x01, 42, 0]);
+        let pieces = [piece(PieceLocation::Empty, 4)];
+        assert!(matches!(
+            with_context(|ctx| read_pieces(&pieces, 4, ctx)),
+            Err(EvalError::OptimizedOut)
+        ));
+    }
+
+    #[test]
+    fn location_lists_select_by_pc() {
+        let location = Location::List(
+            vec![
+                LocationRange {
+                    begin: 0x1000,
+                    end: 0x1010,
+                    expression: vec![0x50],
+                },
+                LocationRange {
+                    begin: 0x1010,
+                    end: 0x1020,
+                    expression: vec![0x91, 0x08],
+                },
+            ],
+            encoding(),
+        );
+        let at = |pc| with_context(|ctx| evaluate_location(&location, pc, ctx));
+        assert_eq!(at(0x1008).unwrap(), whole(PieceLocation::Register(0)));
+        assert_eq!(at(0x1010).unwrap(), whole(PieceLocation::Memory(0x7008)));
+        assert!(matches!(at(0x1020), Err(EvalError::OptimizedOut)));
+    }
+
+    #[test]
+    fn simple_locations() {
+        let location = Location::FramePointerOffset(-8);
+        assert_eq!(
+            with_context(|ctx| evaluate_location(&location, 0x1000, ctx)).unwrap(),
+            whole(PieceLocation::Memory(0x6ff8))
+        );
+        // breg7 16, as a frame base
+        let location = Location::Expression(vec![0x77, 0x10], encoding());
+        assert_eq!(
+            with_context(|ctx| evaluate_frame_base(&location, ctx)).unwrap(),
+            0x7000
+        );
+    }
+}
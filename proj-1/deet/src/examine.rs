@@ -0,0 +1,128 @@
+//! Formats for the `x/NFU` memory examine command: a count, a format letter and a unit size.
+
+#[derive(Clone, Copy)]
+pub struct Format {
+    pub count: usize,
+    /// One of x (hex), d (decimal), c (char), s (string) or i (instruction).
+    pub letter: char,
+    /// Unit size in bytes: 1, 2, 4 or 8 for b, h, w and g.
+    pub unit: usize,
+}
+
+impl Format {
+    pub fn new() -> Format {
+        Format {
+            count: 1,
+            letter: 'x',
+            unit: 4,
+        }
+    }
+
+    /// Parses the part of `x/NFU` after the slash. As in gdb, a format letter or unit size that
+    /// is left out is the one used last time, given as `previous`, and the count defaults to 1.
+    pub fn parse(spec: &str, previous: Format) -> Result<Format, String> {
+        let digits = spec.chars().take_while(|c| c.is_ascii_digit()).count();
+        let mut format = Format {
+            count: if digits == 0 {
+                1
+            } else {
+                spec[..digits]
+                    .parse()
+                    .map_err(|_| format!("Invalid count \"{}\"", &spec[..digits]))?
+            },
+            ..previous
+        };
+        for c in spec[digits..].chars() {
+            match c {
+                'x' | 'd' | 'c' | 's' | 'i' => format.letter = c,
+                'b' => format.unit = 1,
+                'h' => format.unit = 2,
+                'w' => format.unit = 4,
+                'g' => format.unit = 8,
+                _ => return Err(format!("Undefined output format \"{}\".", c)),
+            }
+        }
+        if format.letter == 'c' {
+            format.unit = 1;
+        }
+        Ok(format)
+    }
+
+    /// Number of units printed on each line.
+    pub fn per_line(&self) -> usize {
+        match self.unit {
+            1 | 2 => 8,
+            4 => 4,
+            _ => 2,
+        }
+    }
+
+    /// Formats one unit of memory, given its little-endian bytes.
+    pub fn format_unit(&self, bytes: &[u8]) -> String {
+        let mut raw = [0u8; 8];
+        raw[..bytes.len()].copy_from_slice(bytes);
+        let value = u64::from_le_bytes(raw);
+        match self.letter {
+            'd' => {
+                let shift = 64 - 8 * bytes.len();
+                format!("{}", ((value << shift) as i64) >> shift)
+            }
+            'c' => format!("{} {:?}", bytes[0] as i8, bytes[0] as char),
+            _ => format!("{:#0width$x}", value, width = 2 + 2 * bytes.len()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn previous() -> Format {
+        Format {
+            count: 16,
+            letter: 'd',
+            unit: 8,
+        }
+    }
+
+    #[test]
+    fn parse_inherits_letter_and_unit() {
+        let format = Format::parse("4", previous()).unwrap();
+        assert_eq!((format.count, format.letter, format.unit), (4, 'd', 8));
+    }
+
+    #[test]
+    fn parse_does_not_inherit_count() {
+        let format = Format::parse("", previous()).unwrap();
+        assert_eq!((format.count, format.letter, format.unit), (1, 'd', 8));
+    }
+
+    #[test]
+    fn parse_overrides_letter_and_unit_separately() {
+        let format = Format::parse("x", previous()).unwrap();
+        assert_eq!((format.count, format.letter, format.unit), (1, 'x', 8));
+        let format = Format::parse("2h", previous()).unwrap();
+        assert_eq!((format.count, format.letter, format.unit), (2, 'd', 2));
+        let format = Format::parse("8xb", previous()).unwrap();
+        assert_eq!((format.count, format.letter, format.unit), (8, 'x', 1));
+    }
+
+    #[test]
+    fn parse_uses_bytes_for_chars() {
+        let format = Format::parse("c", previous()).unwrap();
+        assert_eq!((format.letter, format.unit), ('c', 1));
+    }
+
+    #[test]
+    fn parse_rejects_unknown_letters() {
+        assert!(Format::parse("4q", previous()).is_err());
+    }
+
+    #[test]
+    fn format_unit_sign_extends_decimals() {
+        let format = Format::parse("dh", previous()).unwrap();
+        assert_eq!(format.format_unit(&[0xfe, 0xff]), "-2");
+        let format = Format::parse("xh", previous()).unwrap();
+        assert_eq!(format.format_unit(&[0xfe, 0xff]), "0xfffe");
+    }
+}
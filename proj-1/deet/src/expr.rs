@@ -0,0 +1,317 @@
+//! A small C-like expression language for breakpoint conditions, e.g. `i == 10 && !done`.
+//! Values are 64-bit integers; variables are looked up by the caller.
+
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UnaryOp {
+    Neg,
+    Not,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BinaryOp {
+    Mul,
+    Div,
+    Mod,
+    Add,
+    Sub,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+    Ne,
+    And,
+    Or,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Number(i64),
+    Variable(String),
+    Unary(UnaryOp, Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(i64),
+    Ident(String),
+    Op(&'static str),
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Number(value) => write!(f, "{}", value),
+            Token::Ident(name) => write!(f, "{}", name),
+            Token::Op(op) => write!(f, "{}", op),
+        }
+    }
+}
+
+// Longer operators come first so that e.g. "<=" isn't lexed as "<" followed by "="
+const OPERATORS: [&str; 16] = [
+    "==", "!=", "<=", ">=", "&&", "||", "<", ">", "+", "-", "*", "/", "%", "!", "(", ")",
+];
+
+fn tokenize(input: &str) -> Result<Vec<Token>, String> {
+    let chars: Vec<char> = input.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+        } else if c.is_ascii_digit() {
+            let start = i;
+            while i < chars.len() && chars[i].is_ascii_alphanumeric() {
+                i += 1;
+            }
+            let literal: String = chars[start..i].iter().collect();
+            let value = if literal.starts_with("0x") || literal.starts_with("0X") {
+                i64::from_str_radix(&literal[2..], 16)
+            } else {
+                literal.parse()
+            };
+            tokens.push(Token::Number(
+                value.map_err(|_| format!("Invalid number \"{}\"", literal))?,
+            ));
+        } else if c == '\'' {
+            // Character literal, e.g. 'a'
+            match (chars.get(i + 1), chars.get(i + 2)) {
+                (Some(value), Some('\'')) => tokens.push(Token::Number(*value as i64)),
+                _ => return Err("Invalid character constant".to_string()),
+            }
+            i += 3;
+        } else if c.is_alphabetic() || c == '_' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            tokens.push(Token::Ident(chars[start..i].iter().collect()));
+        } else {
+            let rest: String = chars[i..].iter().collect();
+            match OPERATORS.iter().find(|op| rest.starts_with(*op)) {
+                Some(op) => {
+                    tokens.push(Token::Op(*op));
+                    i += op.len();
+                }
+                None => return Err(format!("Invalid character '{}' in expression", c)),
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+/// Binary operators from lowest to highest precedence.
+const PRECEDENCE: [&[(&str, BinaryOp)]; 6] = [
+    &[("||", BinaryOp::Or)],
+    &[("&&", BinaryOp::And)],
+    &[("==", BinaryOp::Eq), ("!=", BinaryOp::Ne)],
+    &[
+        ("<", BinaryOp::Lt),
+        ("<=", BinaryOp::Le),
+        (">", BinaryOp::Gt),
+        (">=", BinaryOp::Ge),
+    ],
+    &[("+", BinaryOp::Add), ("-", BinaryOp::Sub)],
+    &[
+        ("*", BinaryOp::Mul),
+        ("/", BinaryOp::Div),
+        ("%", BinaryOp::Mod),
+    ],
+];
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn parse_binary(&mut self, level: usize) -> Result<Expr, String> {
+        if level == PRECEDENCE.len() {
+            return self.parse_unary();
+        }
+        let mut lhs = self.parse_binary(level + 1)?;
+        loop {
+            let op = match self.peek() {
+                Some(Token::Op(token)) => PRECEDENCE[level]
+                    .iter()
+                    .find(|(text, _)| text == token)
+                    .map(|(_, op)| *op),
+                _ => None,
+            };
+            match op {
+                Some(op) => {
+                    self.pos += 1;
+                    let rhs = self.parse_binary(level + 1)?;
+                    lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
+                }
+                None => return Ok(lhs),
+            }
+        }
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr, String> {
+        match self.peek() {
+            Some(Token::Op("-")) => {
+                self.pos += 1;
+                Ok(Expr::Unary(UnaryOp::Neg, Box::new(self.parse_unary()?)))
+            }
+            Some(Token::Op("!")) => {
+                self.pos += 1;
+                Ok(Expr::Unary(UnaryOp::Not, Box::new(self.parse_unary()?)))
+            }
+            _ => self.parse_primary(),
+        }
+    }
+
+    fn parse_primary(&mut self) -> Result<Expr, String> {
+        match self.next() {
+            Some(Token::Number(value)) => Ok(Expr::Number(value)),
+            Some(Token::Ident(name)) => Ok(Expr::Variable(name)),
+            Some(Token::Op("(")) => {
+                let expr = self.parse_binary(0)?;
+                match self.next() {
+                    Some(Token::Op(")")) => Ok(expr),
+                    _ => Err("Expected ')'".to_string()),
+                }
+            }
+            Some(token) => Err(format!("Unexpected \"{}\" in expression", token)),
+            None => Err("Unexpected end of expression".to_string()),
+        }
+    }
+}
+
+/// Parses an expression such as `i == 10 || (count % 100) == 0`.
+pub fn parse(input: &str) -> Result<Expr, String> {
+    let mut parser = Parser {
+        tokens: tokenize(input)?,
+        pos: 0,
+    };
+    let expr = parser.parse_binary(0)?;
+    match parser.peek() {
+        Some(token) => Err(format!("Unexpected \"{}\" in expression", token)),
+        None => Ok(expr),
+    }
+}
+
+impl Expr {
+    /// Evaluates the expression, calling `lookup` to get the value of each variable it uses.
+    pub fn evaluate(&self, lookup: &dyn Fn(&str) -> Result<i64, String>) -> Result<i64, String> {
+        Ok(match self {
+            Expr::Number(value) => *value,
+            Expr::Variable(name) => lookup(name)?,
+            Expr::Unary(UnaryOp::Neg, operand) => operand.evaluate(lookup)?.wrapping_neg(),
+            Expr::Unary(UnaryOp::Not, operand) => (operand.evaluate(lookup)? == 0) as i64,
+            // && and || short-circuit, as in C
+            Expr::Binary(BinaryOp::And, lhs, rhs) => {
+                (lhs.evaluate(lookup)? != 0 && rhs.evaluate(lookup)? != 0) as i64
+            }
+            Expr::Binary(BinaryOp::Or, lhs, rhs) => {
+                (lhs.evaluate(lookup)? != 0 || rhs.evaluate(lookup)? != 0) as i64
+            }
+            Expr::Binary(op, lhs, rhs) => {
+                let a = lhs.evaluate(lookup)?;
+                let b = rhs.evaluate(lookup)?;
+                match op {
+                    BinaryOp::Mul => a.wrapping_mul(b),
+                    BinaryOp::Div | BinaryOp::Mod if b == 0 => {
+                        return Err("Division by zero".to_string())
+                    }
+                    BinaryOp::Div => a.wrapping_div(b),
+                    BinaryOp::Mod => a.wrapping_rem(b),
+                    BinaryOp::Add => a.wrapping_add(b),
+                    BinaryOp::Sub => a.wrapping_sub(b),
+                    BinaryOp::Lt => (a < b) as i64,
+                    BinaryOp::Le => (a <= b) as i64,
+                    BinaryOp::Gt => (a > b) as i64,
+                    BinaryOp::Ge => (a >= b) as i64,
+                    BinaryOp::Eq => (a == b) as i64,
+                    BinaryOp::Ne => (a != b) as i64,
+                    BinaryOp::And | BinaryOp::Or => unreachable!(),
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(input: &str) -> i64 {
+        let lookup = |name: &str| match name {
+            "i" => Ok(10),
+            "done" => Ok(0),
+            _ => Err(format!("No symbol \"{}\"", name)),
+        };
+        parse(input).unwrap().evaluate(&lookup).unwrap()
+    }
+
+    #[test]
+    fn multiplication_binds_tighter_than_addition() {
+        assert_eq!(eval("1 + 2 * 3"), 7);
+        assert_eq!(eval("(1 + 2) * 3"), 9);
+        assert_eq!(eval("10 - 4 % 3"), 9);
+    }
+
+    #[test]
+    fn operators_of_equal_precedence_associate_left() {
+        assert_eq!(eval("10 - 4 - 3"), 3);
+        assert_eq!(eval("100 / 10 / 5"), 2);
+    }
+
+    #[test]
+    fn comparisons_bind_tighter_than_logic() {
+        assert_eq!(eval("i == 10 && !done"), 1);
+        assert_eq!(eval("i < 5 || i >= 10"), 1);
+        assert_eq!(eval("1 < 2 == 1"), 1);
+        assert_eq!(eval("0 && 1 || 1"), 1);
+        assert_eq!(eval("1 || 0 && 0"), 1);
+    }
+
+    #[test]
+    fn unary_operators_bind_tightest() {
+        assert_eq!(eval("-2 * 3"), -6);
+        assert_eq!(eval("!0 + 1"), 2);
+        assert_eq!(eval("- -i"), 10);
+    }
+
+    #[test]
+    fn parse_builds_tree_by_precedence() {
+        let expected = Expr::Binary(
+            BinaryOp::Or,
+            Box::new(Expr::Binary(
+                BinaryOp::Eq,
+                Box::new(Expr::Variable("i".to_string())),
+                Box::new(Expr::Number(10)),
+            )),
+            Box::new(Expr::Binary(
+                BinaryOp::And,
+                Box::new(Expr::Number(1)),
+                Box::new(Expr::Unary(UnaryOp::Not, Box::new(Expr::Number(0)))),
+            )),
+        );
+        assert_eq!(parse("i == 10 || 1 && !0"), Ok(expected));
+    }
+
+    #[test]
+    fn parse_rejects_malformed_input() {
+        assert!(parse("(1 + 2").is_err());
+        assert!(parse("1 +").is_err());
+        assert!(parse("1 2").is_err());
+    }
+}
@@ -10,13 +10,18 @@ use gimli::{UnitOffset, UnitSectionOffset};
 use object::Object;
 use std::borrow;
 //use std::io::{BufWriter, Write};
-use crate::dwarf_data::{File, Function, Line, Location, Type, Variable};
+use crate::dwarf_data::{
+    File, Function, Line, Location, LocationRange, Member, Type, TypeKind, Variable,
+};
 use std::collections::HashMap;
 use std::convert::TryInto;
 use std::fmt::Write;
 use std::{io, path};
 
-pub fn load_file(object: &object::File, endian: gimli::RunTimeEndian) -> Result<Vec<File>, Error> {
+pub fn load_file(
+    object: &object::File,
+    endian: gimli::RunTimeEndian,
+) -> Result<(Vec<File>, HashMap<usize, Type>), Error> {
     // Load a section and return as `Cow<[u8]>`.
     let load_section = |id: gimli::SectionId| -> Result<borrow::Cow<[u8]>, gimli::Error> {
         Ok(object
@@ -49,12 +54,16 @@ pub fn load_file(object: &object::File, endian: gimli::RunTimeEndian) -> Result<
     while let Some(header) = iter.next()? {
         let unit = dwarf.unit(header)?;
 
+        // Collect the unit's types first, so that variables can refer to types declared after
+        // them
+        let added = load_types(&unit, &dwarf, &mut offset_to_type)?;
+        resolve_derived_types(&mut offset_to_type, &added);
+
         // Iterate over the Debugging Information Entries (DIEs) in the unit.
         let mut depth = 0;
         let mut entries = unit.entries();
         while let Some((delta_depth, entry)) = entries.next_dfs()? {
             depth += delta_depth;
-            // Update the offset_to_type mapping for types
             // Update the variable list for formal params/variables
             match entry.tag() {
                 gimli::DW_TAG_compile_unit => {
@@ -69,38 +78,14 @@ pub fn load_file(object: &object::File, endian: gimli::RunTimeEndian) -> Result<
                     };
                     compilation_units.push(File {
                         name,
+                        comp_dir: unit
+                            .comp_dir
+                            .map(|comp_dir| comp_dir.to_string_lossy().into_owned()),
                         global_variables: Vec::new(),
                         functions: Vec::new(),
                         lines: Vec::new(),
                     });
                 }
-                gimli::DW_TAG_base_type => {
-                    let name = if let Ok(Some(attr)) = entry.attr(gimli::DW_AT_name) {
-                        if let Ok(DebugValue::Str(name)) = get_attr_value(&attr, &unit, &dwarf) {
-                            name
-                        } else {
-                            "<unknown>".to_string()
-                        }
-                    } else {
-                        "<unknown>".to_string()
-                    };
-                    let byte_size = if let Ok(Some(attr)) = entry.attr(gimli::DW_AT_byte_size) {
-                        if let Ok(DebugValue::Uint(byte_size)) =
-                            get_attr_value(&attr, &unit, &dwarf)
-                        {
-                            byte_size
-                        } else {
-                            // TODO: report error?
-                            0
-                        }
-                    } else {
-                        // TODO: report error?
-                        0
-                    };
-                    let type_offset = entry.offset().0;
-                    offset_to_type
-                        .insert(type_offset, Type::new(name, byte_size.try_into().unwrap()));
-                }
                 gimli::DW_TAG_subprogram => {
                     let mut func: Function = Default::default();
                     let mut attrs = entry.attrs();
@@ -129,6 +114,14 @@ pub fn load_file(object: &object::File, endian: gimli::RunTimeEndian) -> Result<
                                     func.line_number = line_number.try_into().unwrap();
                                 }
                             }
+                            gimli::DW_AT_type => {
+                                if let Ok(DebugValue::Size(offset)) = val {
+                                    func.return_type = offset_to_type.get(&offset).cloned();
+                                }
+                            }
+                            gimli::DW_AT_frame_base => {
+                                func.frame_base = get_location(&attr, &unit, &dwarf);
+                            }
                             _ => {}
                         }
                     }
@@ -157,7 +150,7 @@ pub fn load_file(object: &object::File, endian: gimli::RunTimeEndian) -> Result<
                                 }
                             }
                             gimli::DW_AT_location => {
-                                if let Some(loc) = get_location(&attr, &unit) {
+                                if let Some(loc) = get_location(&attr, &unit, &dwarf) {
                                     location = Some(loc);
                                 }
                             }
@@ -240,7 +233,263 @@ pub fn load_file(object: &object::File, endian: gimli::RunTimeEndian) -> Result<
             }
         }
     }
-    Ok(compilation_units)
+    Ok((compilation_units, offset_to_type))
+}
+
+/// Records every type declared in `unit` into `types`, keyed by its offset in .debug_info.
+/// Members, enumerators and array bounds are attached to the type DIE that owns them.
+/// Returns the offsets of the types added.
+fn load_types<R: Reader>(
+    unit: &gimli::Unit<R>,
+    dwarf: &gimli::Dwarf<R>,
+    types: &mut HashMap<usize, Type>,
+) -> Result<Vec<usize>, Error> {
+    let mut added = Vec::new();
+    // Offsets of the DIEs enclosing the current one, outermost first
+    let mut parents: Vec<usize> = Vec::new();
+    let mut depth: isize = 0;
+    let mut entries = unit.entries();
+    while let Some((delta_depth, entry)) = entries.next_dfs()? {
+        depth += delta_depth;
+        let offset = section_offset(entry.offset(), unit);
+        parents.truncate(depth as usize);
+        let parent = parents.last().cloned();
+        parents.push(offset);
+
+        let name = get_name(entry, unit, dwarf);
+        let byte_size = get_udata(entry, gimli::DW_AT_byte_size).unwrap_or(0) as usize;
+        let type_offset = get_type_offset(entry, unit, dwarf);
+        let ty = match entry.tag() {
+            gimli::DW_TAG_base_type => {
+                Type::new(name.unwrap_or_else(|| "<unknown>".to_string()), byte_size)
+            }
+            gimli::DW_TAG_pointer_type => {
+                Type::with_kind(String::new(), 8, TypeKind::Pointer(type_offset))
+            }
+            gimli::DW_TAG_typedef => Type::with_kind(
+                name.unwrap_or_else(|| "<unknown>".to_string()),
+                0,
+                TypeKind::Typedef(type_offset),
+            ),
+            // Qualifiers don't change how a value is read, so they stand for the qualified type
+            // just as a typedef does, without a name of their own
+            gimli::DW_TAG_const_type
+            | gimli::DW_TAG_volatile_type
+            | gimli::DW_TAG_restrict_type => {
+                Type::with_kind(String::new(), 0, TypeKind::Typedef(type_offset))
+            }
+            gimli::DW_TAG_structure_type | gimli::DW_TAG_union_type => {
+                let keyword = if entry.tag() == gimli::DW_TAG_union_type {
+                    "union"
+                } else {
+                    "struct"
+                };
+                Type::with_kind(
+                    format!(
+                        "{} {}",
+                        keyword,
+                        name.unwrap_or_else(|| "{...}".to_string())
+                    ),
+                    byte_size,
+                    TypeKind::Struct(Vec::new()),
+                )
+            }
+            gimli::DW_TAG_enumeration_type => Type::with_kind(
+                format!("enum {}", name.unwrap_or_else(|| "{...}".to_string())),
+                byte_size,
+                TypeKind::Enum(Vec::new()),
+            ),
+            gimli::DW_TAG_array_type => match type_offset {
+                Some(element) => Type::with_kind(
+                    String::new(),
+                    byte_size,
+                    TypeKind::Array {
+                        element,
+                        dimensions: Vec::new(),
+                    },
+                ),
+                None => continue,
+            },
+            gimli::DW_TAG_member => {
+                if let (Some(type_offset), Some(parent)) = (type_offset, parent) {
+                    if let Some(TypeKind::Struct(members)) =
+                        types.get_mut(&parent).map(|ty| &mut ty.kind)
+                    {
+                        let (offset, bit_field) = member_position(entry, byte_size);
+                        members.push(Member {
+                            name: name.unwrap_or_default(),
+                            offset,
+                            type_offset,
+                            bit_field,
+                        });
+                    }
+                }
+                continue;
+            }
+            gimli::DW_TAG_enumerator => {
+                let value = entry
+                    .attr_value(gimli::DW_AT_const_value)?
+                    .and_then(|value| {
+                        value
+                            .udata_value()
+                            .map(|value| value as i64)
+                            .or_else(|| value.sdata_value())
+                    });
+                if let (Some(value), Some(parent)) = (value, parent) {
+                    if let Some(TypeKind::Enum(enumerators)) =
+                        types.get_mut(&parent).map(|ty| &mut ty.kind)
+                    {
+                        enumerators.push((name.unwrap_or_default(), value));
+                    }
+                }
+                continue;
+            }
+            gimli::DW_TAG_subrange_type => {
+                // Flexible array members have neither a count nor an upper bound
+                let count = match get_udata(entry, gimli::DW_AT_count) {
+                    Some(count) => count as usize,
+                    None => get_udata(entry, gimli::DW_AT_upper_bound)
+                        .map(|bound| bound as usize + 1)
+                        .unwrap_or(0),
+                };
+                if let Some(parent) = parent {
+                    if let Some(TypeKind::Array { dimensions, .. }) =
+                        types.get_mut(&parent).map(|ty| &mut ty.kind)
+                    {
+                        dimensions.push(count);
+                    }
+                }
+                continue;
+            }
+            _ => continue,
+        };
+        types.insert(offset, ty);
+        added.push(offset);
+    }
+    Ok(added)
+}
+
+/// Fills in the names and sizes of the pointer, array, typedef and qualified types among
+/// `offsets`, which DWARF leaves to be derived from the types they refer to.
+fn resolve_derived_types(types: &mut HashMap<usize, Type>, offsets: &[usize]) {
+    for &offset in offsets {
+        let name = derived_type_name(types, Some(offset), 0);
+        let size = derived_type_size(types, offset, 0);
+        let ty = types.get_mut(&offset).unwrap();
+        ty.name = name;
+        ty.size = size;
+    }
+}
+
+// Types can refer to each other in cycles (e.g. a struct holding a pointer to itself), but named
+// types stop the recursion. The depth limit only guards against malformed DWARF.
+const MAX_TYPE_DEPTH: usize = 16;
+
+fn derived_type_name(types: &HashMap<usize, Type>, offset: Option<usize>, depth: usize) -> String {
+    let ty = match offset.and_then(|offset| types.get(&offset)) {
+        Some(ty) => ty,
+        None => return "void".to_string(),
+    };
+    if !ty.name.is_empty() || depth > MAX_TYPE_DEPTH {
+        return ty.name.clone();
+    }
+    match &ty.kind {
+        TypeKind::Pointer(pointee) => {
+            format!("{} *", derived_type_name(types, *pointee, depth + 1))
+        }
+        TypeKind::Array {
+            element,
+            dimensions,
+        } => {
+            let mut name = format!("{} ", derived_type_name(types, Some(*element), depth + 1));
+            for count in dimensions {
+                name.push_str(&format!("[{}]", count));
+            }
+            name
+        }
+        // Only qualified types are typedefs without a name
+        TypeKind::Typedef(target) => derived_type_name(types, *target, depth + 1),
+        _ => ty.name.clone(),
+    }
+}
+
+fn derived_type_size(types: &HashMap<usize, Type>, offset: usize, depth: usize) -> usize {
+    let ty = match types.get(&offset) {
+        Some(ty) => ty,
+        None => return 0,
+    };
+    if ty.size != 0 || depth > MAX_TYPE_DEPTH {
+        return ty.size;
+    }
+    match &ty.kind {
+        TypeKind::Typedef(Some(target)) => derived_type_size(types, *target, depth + 1),
+        TypeKind::Array {
+            element,
+            dimensions,
+        } => derived_type_size(types, *element, depth + 1) * dimensions.iter().product::<usize>(),
+        _ => ty.size,
+    }
+}
+
+fn section_offset<R: Reader>(offset: UnitOffset, unit: &gimli::Unit<R>) -> usize {
+    match offset.to_unit_section_offset(unit) {
+        UnitSectionOffset::DebugInfoOffset(goff) => goff.0,
+        UnitSectionOffset::DebugTypesOffset(goff) => goff.0,
+    }
+}
+
+fn get_name<R: Reader>(
+    entry: &gimli::DebuggingInformationEntry<R>,
+    unit: &gimli::Unit<R>,
+    dwarf: &gimli::Dwarf<R>,
+) -> Option<String> {
+    match get_attr_value(&entry.attr(gimli::DW_AT_name).ok()??, unit, dwarf) {
+        Ok(DebugValue::Str(name)) => Some(name),
+        _ => None,
+    }
+}
+
+fn get_type_offset<R: Reader>(
+    entry: &gimli::DebuggingInformationEntry<R>,
+    unit: &gimli::Unit<R>,
+    dwarf: &gimli::Dwarf<R>,
+) -> Option<usize> {
+    match get_attr_value(&entry.attr(gimli::DW_AT_type).ok()??, unit, dwarf) {
+        Ok(DebugValue::Size(offset)) => Some(offset),
+        _ => None,
+    }
+}
+
+/// Returns where a struct member is: its byte offset, and for a bitfield, its offset in bits from
+/// there and its width in bits. `byte_size` is the member's DW_AT_byte_size, if any.
+fn member_position<R: Reader>(
+    entry: &gimli::DebuggingInformationEntry<R>,
+    byte_size: usize,
+) -> (usize, Option<(usize, usize)>) {
+    let offset = get_udata(entry, gimli::DW_AT_data_member_location).unwrap_or(0) as usize;
+    let width = match get_udata(entry, gimli::DW_AT_bit_size) {
+        Some(width) => width as usize,
+        None => return (offset, None),
+    };
+    if let Some(bit) = get_udata(entry, gimli::DW_AT_data_bit_offset) {
+        // Counted from the start of the struct
+        let bit = bit as usize;
+        return (bit / 8, Some((bit % 8, width)));
+    }
+    // DWARF 2 and 3 count from the most significant bit of a storage unit of DW_AT_byte_size
+    // bytes at the member's offset
+    let from_msb = get_udata(entry, gimli::DW_AT_bit_offset).unwrap_or(0) as usize;
+    (
+        offset,
+        Some(((8 * byte_size).saturating_sub(from_msb + width), width)),
+    )
+}
+
+fn get_udata<R: Reader>(
+    entry: &gimli::DebuggingInformationEntry<R>,
+    name: gimli::DwAt,
+) -> Option<u64> {
+    entry.attr_value(name).ok()??.udata_value()
 }
 
 #[derive(Debug, Clone)]
@@ -291,25 +540,59 @@ impl<'input, Endian> Reader for gimli::EndianSlice<'input, Endian> where
 
 trait Reader: gimli::Reader<Offset = usize> + Send + Sync {}
 
-fn get_location<R: Reader>(attr: &gimli::Attribute<R>, unit: &gimli::Unit<R>) -> Option<Location> {
-    if let gimli::AttributeValue::Exprloc(ref data) = attr.value() {
-        let encoding = unit.encoding();
-        let mut pc = data.0.clone();
-        if pc.len() > 0 {
+fn get_location<R: Reader>(
+    attr: &gimli::Attribute<R>,
+    unit: &gimli::Unit<R>,
+    dwarf: &gimli::Dwarf<R>,
+) -> Option<Location> {
+    match attr.value() {
+        gimli::AttributeValue::Exprloc(ref data) => {
+            let encoding = unit.encoding();
+            let mut pc = data.0.clone();
+            // Single-operation expressions are by far the most common at -O0
             if let Ok(op) = gimli::Operation::parse(&mut pc, encoding) {
-                match op {
-                    gimli::Operation::FrameOffset { offset } => {
-                        return Some(Location::FramePointerOffset(offset.try_into().unwrap()));
-                    }
-                    gimli::Operation::Address { address } => {
-                        return Some(Location::Address(address.try_into().unwrap()));
+                if pc.len() == 0 {
+                    match op {
+                        gimli::Operation::FrameOffset { offset } => {
+                            return Some(Location::FramePointerOffset(offset.try_into().unwrap()));
+                        }
+                        gimli::Operation::Address { address } => {
+                            return Some(Location::Address(address.try_into().unwrap()));
+                        }
+                        _ => {}
                     }
-                    _ => {}
                 }
             }
+            Some(Location::Expression(
+                data.0.to_slice().ok()?.to_vec(),
+                encoding,
+            ))
+        }
+        gimli::AttributeValue::LocationListsRef(offset) => get_location_list(offset, unit, dwarf),
+        // DWARF 5 DW_FORM_loclistx, an index into the unit's table of location list offsets
+        gimli::AttributeValue::DebugLocListsIndex(index) => {
+            let offset = dwarf.locations_offset(unit, index).ok()?;
+            get_location_list(offset, unit, dwarf)
         }
+        _ => None,
+    }
+}
+
+fn get_location_list<R: Reader>(
+    offset: gimli::LocationListsOffset<usize>,
+    unit: &gimli::Unit<R>,
+    dwarf: &gimli::Dwarf<R>,
+) -> Option<Location> {
+    let mut ranges = Vec::new();
+    let mut locations = dwarf.locations(unit, offset).ok()?;
+    while let Ok(Some(entry)) = locations.next() {
+        ranges.push(LocationRange {
+            begin: entry.range.begin.try_into().unwrap(),
+            end: entry.range.end.try_into().unwrap(),
+            expression: entry.data.0.to_slice().ok()?.to_vec(),
+        });
     }
-    None
+    Some(Location::List(ranges, unit.encoding()))
 }
 
 // based on dwarf_dump.rs
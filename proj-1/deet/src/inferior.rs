@@ -1,14 +1,17 @@
 use crate::dwarf_data::DwarfData;
+use crate::target::Target;
+use crate::unwind::Unwinder;
+use nix::errno::Errno;
 use nix::sys::signal;
+use nix::sys::uio::{self, IoVec, RemoteIoVec};
 use nix::sys::wait::{waitpid, WaitPidFlag, WaitStatus};
 use nix::sys::{ptrace, signal::Signal};
 use nix::unistd::Pid;
-use std::{collections::HashMap, mem::size_of};
-use std::{
-    io,
-    os::unix::prelude::CommandExt,
-    process::{Child, Command},
-};
+use std::{collections::HashMap, fs, mem::size_of, path::Path};
+use std::{io, os::unix::prelude::CommandExt, process::Command};
+
+/// Offset of `u_debugreg` within `struct user` (see <sys/user.h>) on x86-64.
+const DEBUG_REGISTERS_OFFSET: usize = 848;
 
 fn align_addr_to_word(addr: usize) -> usize {
     addr & (-(size_of::<usize>() as isize) as usize)
@@ -25,6 +28,50 @@ pub enum Status {
     /// Indicates the inferior exited due to a signal. Contains the signal that killed the
     /// process.
     Signaled(signal::Signal),
+
+    /// Indicates the inferior exec'd a new program. Contains the instruction pointer it is
+    /// stopped at in the new program.
+    Exec(usize),
+}
+
+/// What to do when the inferior forks.
+#[derive(Clone, Copy)]
+pub struct ForkPolicy {
+    /// Debug the child after a fork, rather than the parent.
+    pub follow_child: bool,
+    /// Let the process we don't follow run freely, rather than holding it stopped.
+    pub detach_on_fork: bool,
+}
+
+impl Default for ForkPolicy {
+    fn default() -> Self {
+        ForkPolicy {
+            follow_child: false,
+            detach_on_fork: true,
+        }
+    }
+}
+
+fn trace_options() -> ptrace::Options {
+    ptrace::Options::PTRACE_O_TRACECLONE
+        | ptrace::Options::PTRACE_O_TRACEFORK
+        | ptrace::Options::PTRACE_O_TRACEEXEC
+}
+
+/// `personality` flag that turns off address space randomization.
+const ADDR_NO_RANDOMIZE: libc::c_ulong = 0x0040000;
+
+/// Turns off address space layout randomization, so that the target is loaded at the same
+/// addresses every run. Like `child_traceme`, this is called in the child before the exec.
+fn child_disable_randomization() -> Result<(), std::io::Error> {
+    // 0xffffffff asks for the current persona without changing it
+    let persona = unsafe { libc::personality(0xffffffff) };
+    if persona == -1
+        || unsafe { libc::personality(persona as libc::c_ulong | ADDR_NO_RANDOMIZE) } == -1
+    {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
 }
 
 /// This function calls ptrace with PTRACE_TRACEME to enable debugging on a process. You should use
@@ -34,28 +81,234 @@ fn child_traceme() -> Result<(), std::io::Error> {
         .map_err(|_| std::io::Error::new(std::io::ErrorKind::Other, "ptrace TRACEME failed"))
 }
 
+/// A thread of the inferior.
+pub struct Thread {
+    /// Our number for the thread, counting from 1 in order of creation.
+    pub id: usize,
+    pub tid: Pid,
+    /// True while the thread is executing, as far as ptrace is concerned.
+    running: bool,
+    /// True if a SIGSTOP is on its way to the thread that should be swallowed rather than
+    /// reported: either one we sent to stop it, or the one every new thread starts with.
+    expect_stop: bool,
+    /// A signal that arrived while we were stopping the thread, to deliver when it resumes.
+    pending_signal: Option<Signal>,
+}
+
+/// The kernel reports int3 traps with si_code SI_KERNEL.
+const SI_KERNEL: i32 = 0x80;
+
+/// Returns true if the last SIGTRAP of thread `tid` came from executing an int3.
+fn trapped_by_int3(tid: Pid) -> Result<bool, nix::Error> {
+    Ok(ptrace::getsiginfo(tid)?.si_code == SI_KERNEL)
+}
+
+/// Rewinds thread `tid` onto the breakpoint it just trapped on, if that's why it stopped.
+/// Returns the breakpoint address if so.
+fn rewind_onto_breakpoint(
+    tid: Pid,
+    break_points: &HashMap<usize, u8>,
+) -> Result<Option<usize>, nix::Error> {
+    let mut regs = ptrace::getregs(tid)?;
+    let addr = (regs.rip as usize).wrapping_sub(1);
+    if !break_points.contains_key(&addr) || !trapped_by_int3(tid)? {
+        return Ok(None);
+    }
+    regs.rip = addr as u64;
+    ptrace::setregs(tid, regs)?;
+    Ok(Some(addr))
+}
+
+/// Waits for a thread that was just created or attached to report its first stop.
+fn wait_for_stop(tid: Pid) -> Result<Signal, nix::Error> {
+    loop {
+        match waitpid(tid, Some(WaitPidFlag::__WALL))? {
+            WaitStatus::Stopped(_, signal) => return Ok(signal),
+            WaitStatus::Exited(..) | WaitStatus::Signaled(..) => {
+                return Err(nix::Error::Sys(Errno::ESRCH))
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Lets go of thread `tid`. Its watchpoints are disarmed first: a thread keeps its debug
+/// registers after we detach, and would die of the SIGTRAP when one fired.
+fn detach_thread(tid: Pid, signal: Option<Signal>) -> Result<(), nix::Error> {
+    // DR7 arms DR0-DR3
+    set_debug_register_of(tid, 7, 0)?;
+    let signal = signal.map_or(0, |signal| signal as usize);
+    let result = unsafe {
+        libc::ptrace(
+            libc::PTRACE_DETACH,
+            tid.as_raw(),
+            std::ptr::null_mut::<libc::c_void>(),
+            signal as *mut libc::c_void,
+        )
+    };
+    Errno::result(result).map(drop)
+}
+
+/// Puts the original bytes back at every breakpoint in the memory of the process that thread
+/// `tid` belongs to.
+fn remove_breakpoints(tid: Pid, break_points: &HashMap<usize, u8>) -> Result<(), nix::Error> {
+    for (addr, orig_byte) in break_points.iter() {
+        write_byte_to(tid, *addr, *orig_byte)?;
+    }
+    Ok(())
+}
+
+/// Writes one byte into the memory of the process that thread `tid` belongs to, returning the
+/// byte that was there before.
+fn write_byte_to(tid: Pid, addr: usize, val: u8) -> Result<u8, nix::Error> {
+    let aligned_addr = align_addr_to_word(addr);
+    let byte_offset = addr - aligned_addr;
+    let word = ptrace::read(tid, aligned_addr as ptrace::AddressType)? as u64;
+    let orig_mode = (word >> (8 * byte_offset)) & 0xff;
+    let masked_word = word & !(0xff << (8 * byte_offset));
+    let updated_word = masked_word | ((val as u64) << (8 * byte_offset));
+    ptrace::write(
+        tid,
+        aligned_addr as ptrace::AddressType,
+        updated_word as *mut std::ffi::c_void,
+    )?;
+    Ok(orig_mode as u8)
+}
+
+/// Sets one of the debug registers, DR0 through DR7, of thread `tid`.
+fn set_debug_register_of(tid: Pid, index: usize, value: usize) -> Result<(), nix::Error> {
+    let offset = DEBUG_REGISTERS_OFFSET + index * size_of::<usize>();
+    let result = unsafe {
+        libc::ptrace(
+            libc::PTRACE_POKEUSER,
+            tid.as_raw(),
+            offset as *mut libc::c_void,
+            value as *mut libc::c_void,
+        )
+    };
+    Errno::result(result).map(drop)
+}
+
+fn tgkill(pid: Pid, tid: Pid, signal: Signal) -> Result<(), nix::Error> {
+    let result =
+        unsafe { libc::syscall(libc::SYS_tgkill, pid.as_raw(), tid.as_raw(), signal as i32) };
+    Errno::result(result).map(drop)
+}
+
+/// Lists the threads of process `pid` according to /proc.
+fn list_threads(pid: Pid) -> Vec<Pid> {
+    match fs::read_dir(format!("/proc/{}/task", pid)) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok()?.file_name().to_str()?.parse().ok())
+            .map(Pid::from_raw)
+            .collect(),
+        Err(_) => vec![pid],
+    }
+}
+
+/// The inferior is run "all-stop": whenever one thread stops, we stop the others too, and we
+/// let them all go again when resuming. Operations on registers act on the current thread,
+/// which is the one that stopped last unless the user selects another.
 pub struct Inferior {
-    child: Child,
+    pid: Pid,
+    /// True if we attached to the process rather than starting it ourselves.
+    attached: bool,
+    threads: Vec<Thread>,
+    current: Pid,
+    next_thread_id: usize,
+    fork_policy: ForkPolicy,
+    /// Threads of processes we stopped following at a fork but hold stopped, one list per
+    /// process.
+    held: Vec<Vec<Pid>>,
+    /// Forked processes whose initial stop arrived before the fork event.
+    early_forks: Vec<Pid>,
+    /// DR0 through DR7 as last set, to program into threads created later.
+    debug_registers: [usize; 8],
 }
 
 impl Inferior {
     /// Attempts to start a new inferior process. Returns Some(Inferior) if successful, or None if
     /// an error is encountered.
-    pub fn new(
-        target: &str,
-        args: &Vec<String>,
-        break_points: &mut HashMap<usize, u8>,
-    ) -> Option<Inferior> {
+    pub fn new(target: &str, args: &Vec<String>, disable_randomization: bool) -> Option<Inferior> {
         let mut cmd = Command::new(target);
         cmd.args(args);
         unsafe {
+            if disable_randomization {
+                cmd.pre_exec(child_disable_randomization);
+            }
             cmd.pre_exec(child_traceme);
         }
         let child = cmd.spawn().ok()?;
-        let mut inferior = Inferior { child };
+        let mut inferior = Inferior::with_main_thread(Pid::from_raw(child.id() as i32), false);
+        // The child stops with a SIGTRAP once it has exec'd the target
+        match inferior.wait(None, &HashMap::new()).ok()? {
+            Status::Stopped(Signal::SIGTRAP, _) => {}
+            _ => return None,
+        }
+        ptrace::setoptions(inferior.pid, trace_options()).ok()?;
+        Some(inferior)
+    }
+
+    /// Attaches to a process that is already running, and all of its threads, and stops it.
+    /// Returns the inferior along with the status it stopped with.
+    pub fn attach(pid: Pid) -> Result<(Inferior, Status), nix::Error> {
+        ptrace::attach(pid)?;
+        let mut inferior = Inferior::with_main_thread(pid, true);
+        for tid in list_threads(pid) {
+            // Threads may exit while we're at it
+            if tid != pid && ptrace::attach(tid).is_ok() {
+                inferior.add_thread(tid);
+            }
+        }
+        // PTRACE_ATTACH sends each thread a SIGSTOP; wait for them to take effect before touching
+        // memory
+        let signal = wait_for_stop(pid)?;
+        let main_thread = inferior.thread_mut(pid).unwrap();
+        main_thread.running = false;
+        main_thread.expect_stop = signal != Signal::SIGSTOP;
+        // No breakpoints are inserted yet
+        inferior.stop_others(&HashMap::new())?;
+        for thread in inferior.threads.iter() {
+            ptrace::setoptions(thread.tid, trace_options())?;
+        }
+        let rip = ptrace::getregs(pid)?.rip as usize;
+        Ok((inferior, Status::Stopped(signal, rip)))
+    }
+
+    fn with_main_thread(pid: Pid, attached: bool) -> Inferior {
+        Inferior {
+            pid,
+            attached,
+            threads: vec![Thread {
+                id: 1,
+                tid: pid,
+                running: true,
+                expect_stop: false,
+                pending_signal: None,
+            }],
+            current: pid,
+            next_thread_id: 2,
+            fork_policy: ForkPolicy::default(),
+            held: Vec::new(),
+            early_forks: Vec::new(),
+            debug_registers: [0; 8],
+        }
+    }
+
+    /// Returns true if we attached to this inferior rather than starting it ourselves.
+    pub fn is_attached(&self) -> bool {
+        self.attached
+    }
+
+    pub fn set_fork_policy(&mut self, fork_policy: ForkPolicy) {
+        self.fork_policy = fork_policy;
+    }
+
+    /// Writes a 0xcc to every address in `break_points`, recording the original bytes.
+    pub fn insert_breakpoints(&mut self, break_points: &mut HashMap<usize, u8>) {
         let bp_copy = break_points.clone();
         for addr in bp_copy.keys() {
-            match inferior.write_byte(*addr, 0xcc) {
+            match self.write_byte(*addr, 0xcc) {
                 Ok(orig_mode) => {
                     // println!("Set breakpoint at {:#x}", addr);
                     break_points.insert(*addr, orig_mode);
@@ -65,80 +318,631 @@ impl Inferior {
                 }
             }
         }
-
-        Some(inferior)
     }
 
     /// Returns the pid of this inferior.
     pub fn pid(&self) -> Pid {
-        nix::unistd::Pid::from_raw(self.child.id() as i32)
+        self.pid
+    }
+
+    pub fn threads(&self) -> &[Thread] {
+        &self.threads
+    }
+
+    pub fn current_thread(&self) -> &Thread {
+        self.threads
+            .iter()
+            .find(|thread| thread.tid == self.current)
+            .unwrap()
     }
 
-    /// Calls waitpid on this inferior and returns a Status to indicate the state of the process
-    /// after the waitpid call.
-    pub fn wait(&self, options: Option<WaitPidFlag>) -> Result<Status, nix::Error> {
-        Ok(match waitpid(self.pid(), options)? {
-            WaitStatus::Exited(_pid, exit_code) => Status::Exited(exit_code),
-            WaitStatus::Signaled(_pid, signal, _core_dumped) => Status::Signaled(signal),
-            WaitStatus::Stopped(_pid, signal) => {
-                let regs = ptrace::getregs(self.pid())?;
-                Status::Stopped(signal, regs.rip as usize)
+    /// Makes thread number `id` the current thread. Returns false if there is no such thread.
+    pub fn select_thread(&mut self, id: usize) -> bool {
+        match self.threads.iter().find(|thread| thread.id == id) {
+            Some(thread) => {
+                self.current = thread.tid;
+                true
             }
-            other => panic!("waitpid returned unexpected status: {:?}", other),
-        })
+            None => false,
+        }
     }
 
-    pub fn continue_exec(&self, signal: Option<Signal>) -> Result<Status, nix::Error> {
-        ptrace::cont(self.pid(), signal)?;
-        self.wait(None)
+    /// Returns true if the current thread has a handler installed for `signal`.
+    pub fn catches(&self, signal: Signal) -> bool {
+        let path = format!("/proc/{}/task/{}/status", self.pid, self.current);
+        // SigCgt is a hex mask of the caught signals, with bit 0 for signal 1
+        fs::read_to_string(path)
+            .ok()
+            .and_then(|status| {
+                let mask = status
+                    .lines()
+                    .find_map(|line| line.strip_prefix("SigCgt:"))?;
+                u64::from_str_radix(mask.trim(), 16).ok()
+            })
+            .map_or(false, |mask| mask & (1 << (signal as u64 - 1)) != 0)
     }
-    // pub fn continue_bp(&self) -> Result<Status, nix::Error> {
-    //     ptrace::step(self.pid(), Signal::SIGTRAP);
-    // }
-    pub fn kill(&mut self) -> io::Result<()> {
-        println!("Killing running inferior (pid {})", self.pid());
-        self.child.kill()
+
+    /// Returns the signal the current thread receives when it is next resumed, if any.
+    pub fn pending_signal(&self) -> Option<Signal> {
+        self.current_thread().pending_signal
+    }
+
+    /// Sets the signal the current thread receives when it is next resumed.
+    pub fn set_pending_signal(&mut self, signal: Option<Signal>) {
+        let current = self.current;
+        self.thread_mut(current).unwrap().pending_signal = signal;
     }
-    pub fn print_backtrace(&self, debug_data: &DwarfData) -> Result<(), nix::Error> {
-        let regs = ptrace::getregs(self.pid())?;
-        let mut rip = regs.rip as usize;
-        let mut rbp = regs.rbp as usize;
+
+    fn thread_mut(&mut self, tid: Pid) -> Option<&mut Thread> {
+        self.threads.iter_mut().find(|thread| thread.tid == tid)
+    }
+
+    /// Starts tracking a thread that was just created. It starts out with a SIGSTOP pending.
+    fn add_thread(&mut self, tid: Pid) {
+        if self.threads.iter().any(|thread| thread.tid == tid) {
+            return;
+        }
+        println!("[New Thread {} (LWP {})]", self.next_thread_id, tid);
+        self.threads.push(Thread {
+            id: self.next_thread_id,
+            tid,
+            running: true,
+            expect_stop: true,
+            pending_signal: None,
+        });
+        self.next_thread_id += 1;
+    }
+
+    fn remove_thread(&mut self, tid: Pid) {
+        if let Some(index) = self.threads.iter().position(|thread| thread.tid == tid) {
+            let thread = self.threads.remove(index);
+            println!("[Thread {} (LWP {}) exited]", thread.id, tid);
+        }
+    }
+
+    /// Handles a stop that isn't worth reporting, i.e. the SIGSTOP of `expect_stop`. A thread we
+    /// don't know yet is new, and its initial stop beat the clone (or fork) event to us.
+    fn swallow_stop(&mut self, tid: Pid) -> bool {
+        if self.thread_mut(tid).is_none() {
+            if !Path::new(&format!("/proc/{}/task/{}", self.pid, tid)).exists() {
+                // Not one of our threads, so a forked process
+                self.early_forks.push(tid);
+                return true;
+            }
+            self.add_thread(tid);
+        }
+        let thread = self.thread_mut(tid).unwrap();
+        let expected = thread.expect_stop;
+        thread.expect_stop = false;
+        expected
+    }
+
+    /// Waits for the inferior to stop for a reason worth reporting, keeping track of threads
+    /// being created and exiting along the way. If `stepping` is set, only that thread is running,
+    /// under PTRACE_SINGLESTEP. Once a thread stops, the others are stopped as well, and it
+    /// becomes the current thread.
+    fn wait(
+        &mut self,
+        stepping: Option<Pid>,
+        break_points: &HashMap<usize, u8>,
+    ) -> Result<Status, nix::Error> {
         loop {
-            let file = debug_data.get_line_from_addr(rip);
-            let func = debug_data.get_function_from_addr(rip);
+            let restart = |tid: Pid| {
+                if stepping == Some(tid) {
+                    ptrace::step(tid, None)
+                } else {
+                    ptrace::cont(tid, None)
+                }
+            };
+            match waitpid(stepping, Some(WaitPidFlag::__WALL))? {
+                WaitStatus::Exited(tid, exit_code) if tid == self.pid => {
+                    return Ok(Status::Exited(exit_code))
+                }
+                WaitStatus::Signaled(tid, signal, _core_dumped) if tid == self.pid => {
+                    return Ok(Status::Signaled(signal))
+                }
+                WaitStatus::Exited(tid, _) | WaitStatus::Signaled(tid, _, _) => {
+                    self.remove_thread(tid);
+                    if stepping == Some(tid) && !self.threads.is_empty() {
+                        // The thread we were stepping is gone; report the stop in another one
+                        self.current = self.threads[0].tid;
+                        let rip = ptrace::getregs(self.current)?.rip as usize;
+                        return Ok(Status::Stopped(Signal::SIGTRAP, rip));
+                    }
+                }
+                WaitStatus::PtraceEvent(tid, _, libc::PTRACE_EVENT_CLONE) => {
+                    self.add_thread(Pid::from_raw(ptrace::getevent(tid)? as i32));
+                    restart(tid)?;
+                }
+                WaitStatus::PtraceEvent(tid, _, libc::PTRACE_EVENT_FORK) => {
+                    let child = Pid::from_raw(ptrace::getevent(tid)? as i32);
+                    if !self.fork_policy.follow_child {
+                        self.release_fork_child(child, break_points)?;
+                        restart(tid)?;
+                        continue;
+                    }
+                    self.follow_fork_child(tid, child, break_points)?;
+                    if stepping.is_some() {
+                        // We can't carry on stepping a thread we no longer trace
+                        let rip = ptrace::getregs(child)?.rip as usize;
+                        return Ok(Status::Stopped(Signal::SIGTRAP, rip));
+                    }
+                    ptrace::cont(child, None)?;
+                    self.threads[0].running = true;
+                }
+                WaitStatus::PtraceEvent(_, _, libc::PTRACE_EVENT_EXEC) => {
+                    // Only the thread that called exec survives, and it takes over the process ID
+                    let id = self.next_thread_id;
+                    self.next_thread_id += 1;
+                    self.threads = vec![Thread {
+                        id,
+                        tid: self.pid,
+                        running: false,
+                        expect_stop: false,
+                        pending_signal: None,
+                    }];
+                    self.current = self.pid;
+                    return Ok(Status::Exec(ptrace::getregs(self.pid)?.rip as usize));
+                }
+                WaitStatus::PtraceEvent(tid, _, _) => {
+                    restart(tid)?;
+                }
+                WaitStatus::Stopped(tid, Signal::SIGSTOP) if self.swallow_stop(tid) => {
+                    // A forked process stays stopped until we see its fork event
+                    if !self.early_forks.contains(&tid) {
+                        self.arm_thread(tid)?;
+                        restart(tid)?;
+                    }
+                }
+                WaitStatus::Stopped(tid, signal) => {
+                    if self.thread_mut(tid).is_none() {
+                        self.add_thread(tid);
+                    }
+                    if tid != self.current {
+                        let id = self.thread_mut(tid).unwrap().id;
+                        println!("[Switching to thread {} (LWP {})]", id, tid);
+                        self.current = tid;
+                    }
+                    self.thread_mut(tid).unwrap().running = false;
+                    self.stop_others(break_points)?;
+                    let regs = ptrace::getregs(tid)?;
+                    return Ok(Status::Stopped(signal, regs.rip as usize));
+                }
+                _ => {}
+            }
+        }
+    }
 
-            match (&file, &func) {
-                (Some(file), Some(func)) => {
-                    println!("{} ({})", func, file);
+    /// Stops every thread other than the current one. A thread that hits a breakpoint in the
+    /// meantime is backed up to hit it again when resumed; other signals are held for later.
+    fn stop_others(&mut self, break_points: &HashMap<usize, u8>) -> Result<(), nix::Error> {
+        let mut tids: Vec<Pid> = self
+            .threads
+            .iter()
+            .filter(|thread| thread.running && thread.tid != self.current)
+            .map(|thread| thread.tid)
+            .collect();
+        let mut new_tids = Vec::new();
+        'threads: while let Some(tid) = tids.pop() {
+            if !self.thread_mut(tid).unwrap().expect_stop {
+                tgkill(self.pid, tid, Signal::SIGSTOP)?;
+            }
+            loop {
+                match waitpid(tid, Some(WaitPidFlag::__WALL))? {
+                    WaitStatus::Stopped(_, Signal::SIGSTOP) => break,
+                    WaitStatus::Stopped(_, Signal::SIGTRAP) => {
+                        rewind_onto_breakpoint(tid, break_points)?;
+                        ptrace::cont(tid, None)?;
+                    }
+                    WaitStatus::Stopped(_, signal) => {
+                        self.thread_mut(tid).unwrap().pending_signal = Some(signal);
+                        ptrace::cont(tid, None)?;
+                    }
+                    WaitStatus::PtraceEvent(_, _, libc::PTRACE_EVENT_CLONE) => {
+                        let new_tid = Pid::from_raw(ptrace::getevent(tid)? as i32);
+                        self.add_thread(new_tid);
+                        tids.push(new_tid);
+                        new_tids.push(new_tid);
+                        ptrace::cont(tid, None)?;
+                    }
+                    WaitStatus::PtraceEvent(_, _, libc::PTRACE_EVENT_FORK) => {
+                        // Too late to switch to the child; treat it as if following the parent
+                        let child = Pid::from_raw(ptrace::getevent(tid)? as i32);
+                        self.release_fork_child(child, break_points)?;
+                        ptrace::cont(tid, None)?;
+                    }
+                    WaitStatus::PtraceEvent(..) => {
+                        ptrace::cont(tid, None)?;
+                    }
+                    WaitStatus::Exited(..) | WaitStatus::Signaled(..) => {
+                        self.remove_thread(tid);
+                        continue 'threads;
+                    }
+                    _ => {}
                 }
-                (_, _) => {
-                    println!("%rip register: {:#x}", rip);
+            }
+            if new_tids.contains(&tid) {
+                self.arm_thread(tid)?;
+            }
+            let thread = self.thread_mut(tid).unwrap();
+            thread.running = false;
+            thread.expect_stop = false;
+        }
+        Ok(())
+    }
+
+    /// Waits for the initial stop of a process just forked by the inferior.
+    fn wait_for_fork_child(&mut self, child: Pid) -> Result<(), nix::Error> {
+        match self.early_forks.iter().position(|pid| *pid == child) {
+            Some(index) => {
+                self.early_forks.remove(index);
+            }
+            None => {
+                wait_for_stop(child)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Lets go of the threads of a process we aren't following after a fork, or holds them
+    /// stopped if detach-on-fork is off. The process has a copy of our breakpoints, which have
+    /// to come out first.
+    fn let_go(
+        &mut self,
+        tids: Vec<Pid>,
+        break_points: &HashMap<usize, u8>,
+    ) -> Result<(), nix::Error> {
+        if !self.fork_policy.detach_on_fork {
+            self.held.push(tids);
+            return Ok(());
+        }
+        remove_breakpoints(tids[0], break_points)?;
+        for tid in tids {
+            detach_thread(tid, None)?;
+        }
+        Ok(())
+    }
+
+    /// Deals with a process the inferior just forked, while we carry on following the parent.
+    fn release_fork_child(
+        &mut self,
+        child: Pid,
+        break_points: &HashMap<usize, u8>,
+    ) -> Result<(), nix::Error> {
+        self.wait_for_fork_child(child)?;
+        if self.fork_policy.detach_on_fork {
+            println!("[Detaching after fork from child process {}]", child);
+        } else {
+            println!("[Holding child process {} stopped]", child);
+        }
+        self.let_go(vec![child], break_points)
+    }
+
+    /// Switches to debugging the process that thread `tid` just forked, letting go of the
+    /// parent. The child is left stopped.
+    fn follow_fork_child(
+        &mut self,
+        tid: Pid,
+        child: Pid,
+        break_points: &HashMap<usize, u8>,
+    ) -> Result<(), nix::Error> {
+        self.wait_for_fork_child(child)?;
+        println!(
+            "[Attaching after process {} fork to child process {}]",
+            self.pid, child
+        );
+        // All of the parent's threads have to be stopped to detach them
+        self.current = tid;
+        self.thread_mut(tid).unwrap().running = false;
+        self.stop_others(break_points)?;
+        if self.fork_policy.detach_on_fork {
+            println!("[Detaching after fork from parent process {}]", self.pid);
+        } else {
+            println!("[Holding parent process {} stopped]", self.pid);
+        }
+        let parent_threads = self.threads.iter().map(|thread| thread.tid).collect();
+        self.let_go(parent_threads, break_points)?;
+
+        let id = self.next_thread_id;
+        self.next_thread_id += 1;
+        self.pid = child;
+        self.attached = false;
+        self.threads = vec![Thread {
+            id,
+            tid: child,
+            running: false,
+            expect_stop: false,
+            pending_signal: None,
+        }];
+        self.current = child;
+        // The child has copies of the watched variables, at the same addresses, but no debug
+        // registers of its own
+        self.arm_thread(child)
+    }
+
+    /// Continues the inferior, which may be sitting on one of `break_points`: in that case the
+    /// original instruction is executed first with the trap lifted, and the trap is re-armed
+    /// before carrying on. If the inferior then stops on a breakpoint, %rip is wound back onto
+    /// it, so that the reported address is the breakpoint's and the next resume works the same.
+    pub fn resume(
+        &mut self,
+        signal: Option<Signal>,
+        break_points: &HashMap<usize, u8>,
+    ) -> Result<Status, nix::Error> {
+        let current = self.current;
+        let mut signal = signal.or_else(|| self.thread_mut(current).unwrap().pending_signal.take());
+        let rip = ptrace::getregs(current)?.rip as usize;
+        if break_points.contains_key(&rip) {
+            // The other threads stay stopped so that none of them can slip past the lifted trap
+            match self.single_step(signal, break_points)? {
+                // Landing right on another breakpoint counts as hitting it, and a watchpoint
+                // firing during the step is reported like one firing later
+                Status::Stopped(Signal::SIGTRAP, rip)
+                    if !break_points.contains_key(&rip) && !self.watchpoint_fired()? => {}
+                other => return Ok(other),
+            }
+            signal = None;
+        }
+        for thread in self.threads.iter_mut() {
+            if !thread.running && thread.tid != self.current {
+                ptrace::cont(thread.tid, thread.pending_signal.take())?;
+                thread.running = true;
+            }
+        }
+        ptrace::cont(self.current, signal)?;
+        self.thread_mut(self.current).unwrap().running = true;
+        match self.wait(None, break_points)? {
+            Status::Stopped(Signal::SIGTRAP, rip) => {
+                let rip = rewind_onto_breakpoint(self.current, break_points)?.unwrap_or(rip);
+                Ok(Status::Stopped(Signal::SIGTRAP, rip))
+            }
+            other => Ok(other),
+        }
+    }
+
+    pub fn kill(&mut self) -> io::Result<()> {
+        println!("Killing running inferior (pid {})", self.pid());
+        // Processes held stopped after a fork go too
+        for tids in self.held.iter() {
+            let _ = signal::kill(tids[0], Signal::SIGKILL);
+        }
+        signal::kill(self.pid(), Signal::SIGKILL)
+            .map_err(|err| io::Error::new(io::ErrorKind::Other, err))
+    }
+
+    /// Removes every breakpoint from the inferior and lets it carry on running without us,
+    /// along with any processes held stopped after a fork.
+    pub fn detach(&mut self, break_points: &HashMap<usize, u8>) -> Result<(), nix::Error> {
+        // Threads can only be detached while stopped
+        self.stop_others(break_points)?;
+        remove_breakpoints(self.current, break_points)?;
+        for thread in self.threads.iter_mut() {
+            detach_thread(thread.tid, thread.pending_signal.take())?;
+        }
+        for tids in self.held.drain(..) {
+            remove_breakpoints(tids[0], break_points)?;
+            for tid in tids {
+                detach_thread(tid, None)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Lists the threads along with where each one is, marking the current thread with a `*`.
+    /// `debug_data_at` returns the debugging information that covers an address.
+    pub fn print_threads<'a>(
+        &self,
+        debug_data_at: &dyn Fn(usize) -> &'a DwarfData,
+        unwinder: &Unwinder,
+    ) {
+        println!("  Id   Target Id         Frame");
+        for thread in self.threads.iter() {
+            let marker = if thread.tid == self.current { "*" } else { " " };
+            let target_id = format!("LWP {}", thread.tid);
+            let frame = match ptrace::getregs(thread.tid) {
+                Ok(regs) => {
+                    let pc = regs.rip as usize;
+                    let debug_data = debug_data_at(pc);
+                    match (
+                        debug_data.get_function_from_addr(pc),
+                        debug_data.get_line_from_addr(pc),
+                    ) {
+                        (Some(func), Some(line)) => format!("{} ({})", func, line),
+                        _ => match unwinder.module_name(pc) {
+                            Some(module) => format!("{:#x} in {}", pc, module),
+                            None => format!("{:#x}", pc),
+                        },
+                    }
                 }
+                Err(err) => format!("<unavailable: {}>", err),
+            };
+            println!("{} {:<4} {:<17} {}", marker, thread.id, target_id, frame);
+        }
+    }
+
+    /// Executes a single machine instruction. If a breakpoint has been inserted at the current
+    /// %rip, the original byte is put back for the duration of the step and the 0xcc is re-armed
+    /// afterwards. A signal set to be delivered to the current thread is delivered first.
+    pub fn step_instruction(
+        &mut self,
+        break_points: &HashMap<usize, u8>,
+    ) -> Result<Status, nix::Error> {
+        let current = self.current;
+        let signal = self.thread_mut(current).unwrap().pending_signal.take();
+        self.single_step(signal, break_points)
+    }
+
+    /// After single-stepping the instruction at `prev_rip`, returns the address a call made by
+    /// that instruction will return to, or None if it wasn't a call.
+    pub fn call_return_address(
+        &self,
+        prev_rip: usize,
+        prev_rsp: usize,
+    ) -> Result<Option<usize>, nix::Error> {
+        let rsp = ptrace::getregs(self.current)?.rsp as usize;
+        // A call pushes a return address that points just past the call instruction
+        if rsp == prev_rsp - 8 {
+            let ret_addr = ptrace::read(self.current, rsp as ptrace::AddressType)? as usize;
+            if ret_addr > prev_rip && ret_addr <= prev_rip + 16 {
+                return Ok(Some(ret_addr));
             }
-            if let Some(func) = func {
-                if func == "main" {
-                    break;
+        }
+        Ok(None)
+    }
+
+    fn single_step(
+        &mut self,
+        signal: Option<Signal>,
+        break_points: &HashMap<usize, u8>,
+    ) -> Result<Status, nix::Error> {
+        let tid = self.current;
+        let rip = ptrace::getregs(tid)?.rip as usize;
+        let orig_byte = break_points.get(&rip);
+        if let Some(orig_byte) = orig_byte {
+            self.write_byte(rip, *orig_byte)?;
+        }
+        ptrace::step(tid, signal)?;
+        self.thread_mut(tid).unwrap().running = true;
+        let status = self.wait(Some(tid), break_points)?;
+        if let (Some(_), Status::Stopped(..)) = (orig_byte, &status) {
+            self.write_byte(rip, 0xcc)?;
+        }
+        Ok(status)
+    }
+
+    /// Returns true if the current thread's last SIGTRAP came from executing an int3 (as opposed
+    /// to, say, completing a single step).
+    pub fn trapped_by_breakpoint(&self) -> Result<bool, nix::Error> {
+        trapped_by_int3(self.current)
+    }
+
+    /// Returns the current thread's general-purpose registers.
+    pub fn registers(&self) -> Result<libc::user_regs_struct, nix::Error> {
+        ptrace::getregs(self.current)
+    }
+
+    /// Returns the general-purpose registers of thread `tid`, which needn't be the current one.
+    pub fn thread_registers(&self, tid: Pid) -> Result<libc::user_regs_struct, nix::Error> {
+        ptrace::getregs(tid)
+    }
+
+    /// Overwrites the current thread's general-purpose registers.
+    pub fn set_registers(&mut self, regs: libc::user_regs_struct) -> Result<(), nix::Error> {
+        ptrace::setregs(self.current, regs)
+    }
+
+    /// Reads one of the current thread's debug registers, DR0 through DR7.
+    pub fn debug_register(&self, index: usize) -> Result<usize, nix::Error> {
+        let offset = DEBUG_REGISTERS_OFFSET + index * size_of::<usize>();
+        // PTRACE_PEEKUSER returns the value itself, so -1 is only an error if errno was set
+        Errno::clear();
+        let value = unsafe {
+            libc::ptrace(
+                libc::PTRACE_PEEKUSER,
+                self.current.as_raw(),
+                offset as *mut libc::c_void,
+                std::ptr::null_mut::<libc::c_void>(),
+            )
+        };
+        if value == -1 && Errno::last() != Errno::UnknownErrno {
+            return Err(nix::Error::Sys(Errno::last()));
+        }
+        Ok(value as usize)
+    }
+
+    /// Returns true if DR6 says one of DR0-DR3 has fired since it was last cleared.
+    fn watchpoint_fired(&self) -> Result<bool, nix::Error> {
+        Ok(self.debug_register(6)? & 0b1111 != 0)
+    }
+
+    /// Sets one of the debug registers, in every thread.
+    pub fn set_debug_register(&mut self, index: usize, value: usize) -> Result<(), nix::Error> {
+        for thread in self.threads.iter() {
+            set_debug_register_of(thread.tid, index, value)?;
+        }
+        self.debug_registers[index] = value;
+        Ok(())
+    }
+
+    /// Programs the watchpoints into thread `tid`, which has just made its first stop. New
+    /// threads start out with their debug registers clear.
+    fn arm_thread(&self, tid: Pid) -> Result<(), nix::Error> {
+        if self.debug_registers[7] == 0 {
+            return Ok(());
+        }
+        // DR7 goes last, once the addresses it arms are in place
+        for index in [0, 1, 2, 3, 7] {
+            set_debug_register_of(tid, index, self.debug_registers[index])?;
+        }
+        Ok(())
+    }
+
+    /// Reads `len` bytes of the inferior's memory starting at `addr`, with a single
+    /// process_vm_readv where possible.
+    pub fn read_memory(&self, addr: usize, len: usize) -> Result<Vec<u8>, nix::Error> {
+        // A range that wraps around the address space can't all be mapped
+        addr.checked_add(len)
+            .ok_or(nix::Error::Sys(Errno::EFAULT))?;
+        let mut bytes = vec![0; len];
+        let local = [IoVec::from_mut_slice(&mut bytes)];
+        let remote = [RemoteIoVec { base: addr, len }];
+        match uio::process_vm_readv(self.current, &local, &remote) {
+            Ok(read) if read == len => Ok(bytes),
+            // Not supported by the kernel, or part of the range can only be read through ptrace
+            _ => self.peek_memory(addr, len),
+        }
+    }
+
+    /// Reads memory a word at a time with PTRACE_PEEKDATA.
+    fn peek_memory(&self, addr: usize, len: usize) -> Result<Vec<u8>, nix::Error> {
+        let mut bytes = Vec::with_capacity(len);
+        let mut curr_addr = align_addr_to_word(addr);
+        while curr_addr < addr + len {
+            let word = ptrace::read(self.current, curr_addr as ptrace::AddressType)? as u64;
+            for (i, byte) in word.to_le_bytes().iter().enumerate() {
+                if curr_addr + i >= addr && curr_addr + i < addr + len {
+                    bytes.push(*byte);
                 }
             }
-            rip = ptrace::read(self.pid(), (rbp + 8) as ptrace::AddressType)? as usize;
-            rbp = ptrace::read(self.pid(), rbp as ptrace::AddressType)? as usize;
+            curr_addr += size_of::<usize>();
         }
+        Ok(bytes)
+    }
 
+    /// Writes `bytes` to the inferior's memory starting at `addr`, one word at a time. Unlike
+    /// process_vm_writev, this works on read-only pages such as the program's code.
+    pub fn write_memory(&mut self, addr: usize, bytes: &[u8]) -> Result<(), nix::Error> {
+        let end = addr + bytes.len();
+        let mut curr_addr = align_addr_to_word(addr);
+        while curr_addr < end {
+            let word = ptrace::read(self.current, curr_addr as ptrace::AddressType)? as u64;
+            let mut word = word.to_le_bytes();
+            for (i, byte) in word.iter_mut().enumerate() {
+                if curr_addr + i >= addr && curr_addr + i < end {
+                    *byte = bytes[curr_addr + i - addr];
+                }
+            }
+            ptrace::write(
+                self.current,
+                curr_addr as ptrace::AddressType,
+                u64::from_le_bytes(word) as *mut std::ffi::c_void,
+            )?;
+            curr_addr += size_of::<usize>();
+        }
         Ok(())
     }
 
     pub fn write_byte(&mut self, addr: usize, val: u8) -> Result<u8, nix::Error> {
-        let aligned_addr = align_addr_to_word(addr);
-        let byte_offset = addr - aligned_addr;
-        let word = ptrace::read(self.pid(), aligned_addr as ptrace::AddressType)? as u64;
-        let orig_mode = (word >> (8 * byte_offset)) & 0xff;
-        let masked_word = word & !(0xff << (8 * byte_offset));
-        let updated_word = masked_word | ((val as u64) << (8 * byte_offset));
-        ptrace::write(
-            self.pid(),
-            aligned_addr as ptrace::AddressType,
-            updated_word as *mut std::ffi::c_void,
-        )?;
-        Ok(orig_mode as u8)
+        write_byte_to(self.current, addr, val)
+    }
+}
+
+impl Target for Inferior {
+    fn registers(&self) -> Result<libc::user_regs_struct, nix::Error> {
+        Inferior::registers(self)
+    }
+
+    fn read_memory(&self, addr: usize, len: usize) -> Result<Vec<u8>, nix::Error> {
+        Inferior::read_memory(self, addr, len)
     }
 }
@@ -1,8 +1,22 @@
+mod breakpoint;
+mod coredump;
 mod debugger;
 mod debugger_command;
+mod disassemble;
 mod dwarf_data;
+mod dwarf_expr;
+mod examine;
+mod expr;
 mod gimli_wrapper;
 mod inferior;
+mod memory_map;
+mod registers;
+mod shlib;
+mod signals;
+mod source;
+mod target;
+mod unwind;
+mod watchpoint;
 
 use crate::debugger::Debugger;
 use nix::sys::signal::{signal, SigHandler, Signal};
@@ -10,15 +24,23 @@ use std::env;
 
 fn main() {
     let args: Vec<String> = env::args().collect();
-    if args.len() != 2 {
-        println!("Usage: {} <target program>", args[0]);
-        std::process::exit(1);
-    }
+    let core = match args.len() {
+        2 => None,
+        4 if args[2] == "--core" => Some(&args[3]),
+        _ => {
+            println!("Usage: {} <target program> [--core <core file>]", args[0]);
+            std::process::exit(1);
+        }
+    };
     let target = &args[1];
 
     // Disable handling of ctrl+c in this process (so that ctrl+c only gets delivered to child
     // processes)
     unsafe { signal(Signal::SIGINT, SigHandler::SigIgn) }.expect("Error disabling SIGINT handling");
 
-    Debugger::new(target).run();
+    let mut debugger = Debugger::new(target);
+    if let Some(core) = core {
+        debugger.load_core(core);
+    }
+    debugger.run();
 }
@@ -0,0 +1,91 @@
+//! Parsing of /proc/<pid>/maps, which tells us what the inferior has mapped where.
+
+use nix::unistd::Pid;
+use std::fs;
+use std::io;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mapping {
+    pub start: usize,
+    pub end: usize,
+    pub readable: bool,
+    pub writable: bool,
+    pub executable: bool,
+    /// Offset of the mapping within the backing file.
+    pub offset: usize,
+    /// Backing file, or a pseudo-path like "[stack]". None for anonymous mappings.
+    pub path: Option<String>,
+}
+
+impl Mapping {
+    pub fn contains(&self, addr: usize) -> bool {
+        self.start <= addr && addr < self.end
+    }
+
+    /// Returns true if the mapping is backed by a file on disk.
+    pub fn is_file(&self) -> bool {
+        match &self.path {
+            Some(path) => path.starts_with('/'),
+            None => false,
+        }
+    }
+
+    fn parse(line: &str) -> Option<Mapping> {
+        // Format: start-end perms offset dev inode [path]
+        let mut fields = line.split_whitespace();
+        let mut range = fields.next()?.split('-');
+        let start = usize::from_str_radix(range.next()?, 16).ok()?;
+        let end = usize::from_str_radix(range.next()?, 16).ok()?;
+        let perms = fields.next()?.as_bytes();
+        let offset = usize::from_str_radix(fields.next()?, 16).ok()?;
+        let _dev = fields.next()?;
+        let _inode = fields.next()?;
+        let path: Vec<&str> = fields.collect();
+        Some(Mapping {
+            start,
+            end,
+            readable: perms.first() == Some(&b'r'),
+            writable: perms.get(1) == Some(&b'w'),
+            executable: perms.get(2) == Some(&b'x'),
+            offset,
+            path: if path.is_empty() {
+                None
+            } else {
+                Some(path.join(" "))
+            },
+        })
+    }
+}
+
+/// Reads the memory map of a process.
+pub fn read_maps(pid: Pid) -> io::Result<Vec<Mapping>> {
+    let contents = fs::read_to_string(format!("/proc/{}/maps", pid))?;
+    Ok(contents.lines().filter_map(Mapping::parse).collect())
+}
+
+/// Auxiliary vector key for the address the dynamic linker was loaded at.
+pub const AT_BASE: u64 = 7;
+/// Auxiliary vector key for the program's entry point.
+pub const AT_ENTRY: u64 = 9;
+
+/// Reads entry `key` of a process's auxiliary vector, which the kernel fills in when it starts
+/// a program.
+pub fn read_auxv(pid: Pid, key: u64) -> Option<usize> {
+    let contents = fs::read(format!("/proc/{}/auxv", pid)).ok()?;
+    find_auxv(&contents, key)
+}
+
+/// Looks up entry `key` in the raw contents of an auxiliary vector: pairs of 64-bit key and
+/// value.
+pub fn find_auxv(auxv: &[u8], key: u64) -> Option<usize> {
+    auxv.chunks_exact(16)
+        .map(|entry| {
+            let (entry_key, value) = entry.split_at(8);
+            (
+                u64::from_le_bytes(entry_key.try_into().unwrap()),
+                u64::from_le_bytes(value.try_into().unwrap()),
+            )
+        })
+        .find(|(entry_key, _)| *entry_key == key)
+        .map(|(_, value)| value as usize)
+}
@@ -0,0 +1,170 @@
+//! Shared libraries, found through the dynamic linker's debugger rendezvous: `_r_debug`, which
+//! lists the loaded objects and says where the dynamic linker calls whenever that list changes.
+
+use crate::dwarf_data::DwarfData;
+use crate::memory_map;
+use nix::unistd::Pid;
+use object::Object;
+use std::convert::TryInto;
+use std::fs;
+use std::path::Path;
+
+/// `r_debug.r_state` once the list of loaded objects is complete.
+const RT_CONSISTENT: u32 = 0;
+
+pub struct SharedLibrary {
+    pub path: String,
+    /// Difference between the addresses the library was linked at and where it is loaded.
+    pub bias: usize,
+    /// None if the library has no debugging information we can read.
+    pub debug_data: Option<DwarfData>,
+}
+
+impl SharedLibrary {
+    /// Returns true if the user means this library by `name`, e.g. "libfoo.so" for
+    /// /usr/lib/libfoo.so.1.
+    pub fn is_named(&self, name: &str) -> bool {
+        if self.path == name {
+            return true;
+        }
+        match Path::new(&self.path)
+            .file_name()
+            .and_then(|file| file.to_str())
+        {
+            Some(file) => file == name || file.starts_with(&format!("{}.", name)),
+            None => false,
+        }
+    }
+}
+
+pub struct Rendezvous {
+    /// Address of `_r_debug` in the inferior.
+    r_debug: usize,
+    /// Address of `_dl_debug_state`, if the dynamic linker's symbols include it.
+    debug_state: Option<usize>,
+    /// Where we keep an internal breakpoint. While the dynamic linker is starting up, this is
+    /// the program's entry point, by which time it has filled in `_r_debug`; after that, it is
+    /// the function the dynamic linker calls whenever the list of loaded objects changes.
+    pub breakpoint: usize,
+    /// True while `breakpoint` is the entry point.
+    pub starting: bool,
+}
+
+impl Rendezvous {
+    /// Finds the rendezvous of process `pid` from the symbols of its dynamic linker. Returns None
+    /// for statically linked programs.
+    pub fn find(
+        pid: Pid,
+        read_memory: &dyn Fn(usize, usize) -> Result<Vec<u8>, nix::Error>,
+    ) -> Option<Rendezvous> {
+        let base = memory_map::read_auxv(pid, memory_map::AT_BASE).filter(|base| *base != 0)?;
+        let entry = memory_map::read_auxv(pid, memory_map::AT_ENTRY)?;
+        let maps = memory_map::read_maps(pid).ok()?;
+        let interpreter = maps
+            .iter()
+            .find(|mapping| mapping.contains(base))?
+            .path
+            .as_ref()?;
+        let contents = fs::read(interpreter).ok()?;
+        let object = object::File::parse(&*contents).ok()?;
+        let symbol = |name: &str| {
+            object
+                .dynamic_symbols()
+                .chain(object.symbols())
+                .find(|(_, symbol)| symbol.name() == Some(name))
+                .map(|(_, symbol)| base + symbol.address() as usize)
+        };
+        let mut rendezvous = Rendezvous {
+            r_debug: symbol("_r_debug")?,
+            // Usually only in .symtab, which an installed dynamic linker has had stripped
+            debug_state: symbol("_dl_debug_state"),
+            breakpoint: entry,
+            starting: true,
+        };
+        // When attaching, the dynamic linker has long since started up
+        if let Ok(Some(r_brk)) = rendezvous.read_r_brk(read_memory) {
+            rendezvous.breakpoint = r_brk;
+            rendezvous.starting = false;
+        }
+        Some(rendezvous)
+    }
+
+    /// Moves `breakpoint` on from the entry point, once the inferior has reached it, to where
+    /// the dynamic linker reports changes: `r_debug.r_brk`, or failing that `_dl_debug_state`.
+    /// Returns false if neither is known.
+    pub fn started(
+        &mut self,
+        read_memory: &dyn Fn(usize, usize) -> Result<Vec<u8>, nix::Error>,
+    ) -> Result<bool, nix::Error> {
+        self.starting = false;
+        match self.read_r_brk(read_memory)?.or(self.debug_state) {
+            Some(addr) => {
+                self.breakpoint = addr;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Reads `r_debug.r_brk`, which is 0 until the dynamic linker fills it in.
+    fn read_r_brk(
+        &self,
+        read_memory: &dyn Fn(usize, usize) -> Result<Vec<u8>, nix::Error>,
+    ) -> Result<Option<usize>, nix::Error> {
+        let r_brk = read_word(self.r_debug + 16, read_memory)?;
+        Ok(Some(r_brk).filter(|addr| *addr != 0))
+    }
+
+    /// Returns the path and load bias of each object the dynamic linker has loaded, other than
+    /// the executable itself, or None if it is in the middle of changing the list.
+    pub fn loaded(
+        &self,
+        read_memory: &dyn Fn(usize, usize) -> Result<Vec<u8>, nix::Error>,
+    ) -> Result<Option<Vec<(String, usize)>>, nix::Error> {
+        let read_word = |addr: usize| read_word(addr, read_memory);
+        // struct r_debug { int r_version; struct link_map *r_map; ElfW(Addr) r_brk;
+        //                  enum { RT_CONSISTENT, RT_ADD, RT_DELETE } r_state; ... }
+        let r_state = read_word(self.r_debug + 24)? as u32;
+        if r_state != RT_CONSISTENT {
+            return Ok(None);
+        }
+        let mut libraries = Vec::new();
+        let mut link_map = read_word(self.r_debug + 8)?;
+        while link_map != 0 {
+            // struct link_map { ElfW(Addr) l_addr; char *l_name; ElfW(Dyn) *l_ld;
+            //                   struct link_map *l_next, *l_prev; }
+            let bias = read_word(link_map)?;
+            let name = read_c_string(read_word(link_map + 8)?, read_memory)?;
+            // The executable's own entry has an empty name
+            if !name.is_empty() {
+                libraries.push((name, bias));
+            }
+            link_map = read_word(link_map + 24)?;
+        }
+        Ok(Some(libraries))
+    }
+}
+
+fn read_word(
+    addr: usize,
+    read_memory: &dyn Fn(usize, usize) -> Result<Vec<u8>, nix::Error>,
+) -> Result<usize, nix::Error> {
+    Ok(u64::from_le_bytes(read_memory(addr, 8)?[..].try_into().unwrap()) as usize)
+}
+
+fn read_c_string(
+    addr: usize,
+    read_memory: &dyn Fn(usize, usize) -> Result<Vec<u8>, nix::Error>,
+) -> Result<String, nix::Error> {
+    if addr == 0 {
+        return Ok(String::new());
+    }
+    let mut bytes = Vec::new();
+    loop {
+        let byte = read_memory(addr + bytes.len(), 1)?[0];
+        if byte == 0 {
+            return Ok(String::from_utf8_lossy(&bytes).into_owned());
+        }
+        bytes.push(byte);
+    }
+}
@@ -0,0 +1,111 @@
+//! What to do when the inferior receives a signal, set with the `handle` command.
+
+use nix::sys::signal::Signal;
+
+#[derive(Clone, Copy)]
+pub struct SignalPolicy {
+    /// Stop the inferior and give control back to the user.
+    pub stop: bool,
+    /// Say that the signal arrived.
+    pub print: bool,
+    /// Deliver the signal when the inferior is resumed.
+    pub pass: bool,
+}
+
+/// Finds a signal by name ("SIGUSR1" or "USR1") or by number.
+pub fn parse_signal(name: &str) -> Option<Signal> {
+    if let Ok(number) = name.parse::<i32>() {
+        return Signal::from_c_int(number).ok();
+    }
+    let name = name.to_uppercase();
+    Signal::iterator().find(|signal| {
+        let signal = signal.to_string();
+        signal == name || signal[3..] == name
+    })
+}
+
+pub struct SignalTable {
+    policies: Vec<(Signal, SignalPolicy)>,
+}
+
+impl SignalTable {
+    pub fn new() -> SignalTable {
+        let policies = Signal::iterator()
+            .map(|signal| {
+                let policy = match signal {
+                    // SIGTRAP is how breakpoints and steps are reported, and SIGINT is the user
+                    // pressing ctrl+c to get back to the prompt
+                    Signal::SIGTRAP | Signal::SIGINT => SignalPolicy {
+                        stop: true,
+                        print: true,
+                        pass: false,
+                    },
+                    // Signals programs routinely use for their own purposes
+                    Signal::SIGALRM
+                    | Signal::SIGCHLD
+                    | Signal::SIGURG
+                    | Signal::SIGIO
+                    | Signal::SIGVTALRM
+                    | Signal::SIGPROF
+                    | Signal::SIGWINCH => SignalPolicy {
+                        stop: false,
+                        print: false,
+                        pass: true,
+                    },
+                    _ => SignalPolicy {
+                        stop: true,
+                        print: true,
+                        pass: true,
+                    },
+                };
+                (signal, policy)
+            })
+            .collect();
+        SignalTable { policies }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &(Signal, SignalPolicy)> {
+        self.policies.iter()
+    }
+
+    pub fn get(&self, signal: Signal) -> SignalPolicy {
+        self.policies
+            .iter()
+            .find(|(s, _)| *s == signal)
+            .map(|(_, policy)| *policy)
+            .unwrap()
+    }
+
+    /// Applies `handle` keywords to the policy for `signal`. As in gdb, stopping on a signal
+    /// implies printing it, and not printing it implies not stopping. On an unknown keyword,
+    /// nothing is changed.
+    pub fn handle(&mut self, signal: Signal, keywords: &[String]) -> Result<(), String> {
+        let mut policy = self.get(signal);
+        for keyword in keywords {
+            match keyword.as_str() {
+                "stop" => {
+                    policy.stop = true;
+                    policy.print = true;
+                }
+                "nostop" => policy.stop = false,
+                "print" => policy.print = true,
+                "noprint" => {
+                    policy.print = false;
+                    policy.stop = false;
+                }
+                "pass" | "noignore" => policy.pass = true,
+                "nopass" | "ignore" => policy.pass = false,
+                _ => {
+                    return Err(format!(
+                        "Unrecognized or ambiguous flag word: \"{}\".",
+                        keyword
+                    ))
+                }
+            }
+        }
+        if let Some((_, p)) = self.policies.iter_mut().find(|(s, _)| *s == signal) {
+            *p = policy;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,79 @@
+//! Finding and caching the program's source files, for `list` and for showing where the
+//! inferior stopped.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+pub struct SourceCache {
+    /// Directories added with `directory`, searched after the compilation directory, most
+    /// recently added first. Relative ones are taken relative to the compilation directory.
+    directories: Vec<PathBuf>,
+    /// Lines of each file looked up so far, or None if it couldn't be found.
+    files: RefCell<HashMap<PathBuf, Option<Rc<Vec<String>>>>>,
+}
+
+impl SourceCache {
+    pub fn new() -> SourceCache {
+        SourceCache {
+            directories: Vec::new(),
+            files: RefCell::new(HashMap::new()),
+        }
+    }
+
+    pub fn directories(&self) -> &[PathBuf] {
+        &self.directories
+    }
+
+    pub fn add_directory(&mut self, dir: &str) {
+        self.directories
+            .retain(|existing| existing != Path::new(dir));
+        self.directories.insert(0, PathBuf::from(dir));
+        // Files we couldn't find before may be in the new directory
+        self.files.borrow_mut().retain(|_, lines| lines.is_some());
+    }
+
+    /// Returns the lines of source file `path`, as named in the debugging information of a
+    /// compilation unit built in `comp_dir`.
+    pub fn lines(&self, path: &str, comp_dir: Option<&str>) -> Option<Rc<Vec<String>>> {
+        let key = match comp_dir {
+            Some(comp_dir) => Path::new(comp_dir).join(path),
+            None => PathBuf::from(path),
+        };
+        if let Some(lines) = self.files.borrow().get(&key) {
+            return lines.clone();
+        }
+        let lines = self.find(Path::new(path), comp_dir).and_then(|found| {
+            let contents = fs::read(found).ok()?;
+            let text = String::from_utf8_lossy(&contents);
+            Some(Rc::new(text.lines().map(|line| line.to_string()).collect()))
+        });
+        self.files.borrow_mut().insert(key, lines.clone());
+        lines
+    }
+
+    /// Returns line `number`, counting from 1, of a source file.
+    pub fn line(&self, path: &str, comp_dir: Option<&str>, number: usize) -> Option<String> {
+        self.lines(path, comp_dir)?
+            .get(number.checked_sub(1)?)
+            .cloned()
+    }
+
+    fn find(&self, path: &Path, comp_dir: Option<&str>) -> Option<PathBuf> {
+        let comp_dir = comp_dir.map(Path::new).unwrap_or_else(|| Path::new(""));
+        let mut candidates = vec![comp_dir.join(path)];
+        for dir in self.directories.iter() {
+            let dir = comp_dir.join(dir);
+            if path.is_relative() {
+                candidates.push(dir.join(path));
+            }
+            // The tree may have been moved, so also look for the bare file name
+            if let Some(name) = path.file_name() {
+                candidates.push(dir.join(name));
+            }
+        }
+        candidates.into_iter().find(|candidate| candidate.is_file())
+    }
+}
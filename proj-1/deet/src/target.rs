@@ -0,0 +1,45 @@
+//! The state deet inspects, which is either a live process or a core dump it left behind.
+//! Commands that only look at registers and memory, like `backtrace`, `print` and `x`, go
+//! through `Target` so they work on both.
+
+use crate::dwarf_data::DwarfData;
+use crate::unwind::{Frame, Unwinder};
+
+pub trait Target {
+    /// Returns the current thread's general-purpose registers.
+    fn registers(&self) -> Result<libc::user_regs_struct, nix::Error>;
+
+    /// Reads `len` bytes of memory starting at `addr`.
+    fn read_memory(&self, addr: usize, len: usize) -> Result<Vec<u8>, nix::Error>;
+
+    /// Unwinds the current thread's stack, returning the innermost frame first.
+    fn backtrace(&self, unwinder: &Unwinder) -> Result<Vec<Frame>, nix::Error> {
+        let regs = self.registers()?;
+        let read_memory = |addr: usize, len: usize| self.read_memory(addr, len);
+        Ok(unwinder.backtrace(regs, &read_memory))
+    }
+
+    fn print_backtrace(
+        &self,
+        debug_data: &DwarfData,
+        unwinder: &Unwinder,
+    ) -> Result<(), nix::Error> {
+        for (index, frame) in self.backtrace(unwinder)?.iter().enumerate() {
+            let pc = frame.lookup_pc(index);
+            let file = debug_data.get_line_from_addr(pc);
+            let func = debug_data.get_function_from_addr(pc);
+
+            match (&file, &func) {
+                (Some(file), Some(func)) => {
+                    println!("#{} {} ({})", index, func, file);
+                }
+                (_, _) => match unwinder.module_name(frame.pc) {
+                    Some(module) => println!("#{} {:#x} in {}", index, frame.pc, module),
+                    None => println!("#{} %rip register: {:#x}", index, frame.pc),
+                },
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,450 @@
+//! Stack unwinding driven by the call frame information (CFI) in .eh_frame and .debug_frame.
+//! gimli finds the unwind table row for a pc; this module applies its rules to recover the
+//! caller's registers.
+
+use crate::dwarf_expr::{self, EvalContext, PieceLocation};
+use crate::memory_map::{self, Mapping};
+use gimli::UnwindSection;
+use nix::unistd::Pid;
+use object::{Object, ObjectSection, ObjectSegment};
+use std::fs;
+use std::path::Path;
+
+/// DWARF register number of the return address column on x86-64.
+const RETURN_ADDRESS: u16 = 16;
+
+/// Encoding for CFI expressions. Their operands don't depend on the CIE's version or format, only
+/// on the address size.
+const CFI_ENCODING: gimli::Encoding = gimli::Encoding {
+    address_size: 8,
+    format: gimli::Format::Dwarf32,
+    version: 4,
+};
+
+/// Upper bound on the number of frames we unwind, in case the stack is corrupted into a loop.
+const MAX_FRAMES: usize = 256;
+
+type ReadMemory<'a> = &'a dyn Fn(usize, usize) -> Result<Vec<u8>, nix::Error>;
+
+#[derive(Debug, Clone)]
+pub struct Frame {
+    pub pc: usize,
+    /// Canonical frame address: the value of %rsp in the caller just before the call.
+    pub cfa: usize,
+    /// Registers as recovered for this frame. Caller-saved registers may be stale.
+    pub regs: libc::user_regs_struct,
+}
+
+impl Frame {
+    /// Returns the address to use for looking up this frame's function and line. For every
+    /// frame but the innermost, the pc is a return address, which may belong to the next line
+    /// (or even the next function), so we look up the call instruction instead.
+    pub fn lookup_pc(&self, index: usize) -> usize {
+        if index == 0 {
+            self.pc
+        } else {
+            self.pc - 1
+        }
+    }
+}
+
+enum CfaRule {
+    RegisterAndOffset(u16, i64),
+    Expression(Vec<u8>),
+}
+
+enum RegisterRule {
+    Undefined,
+    SameValue,
+    Offset(i64),
+    ValOffset(i64),
+    Register(u16),
+    Expression(Vec<u8>),
+    ValExpression(Vec<u8>),
+}
+
+/// The rules for recovering the CFA and registers at one pc, copied out of gimli so that they
+/// don't borrow the section data.
+struct UnwindRow {
+    cfa: CfaRule,
+    registers: Vec<(u16, RegisterRule)>,
+}
+
+impl UnwindRow {
+    fn from_gimli(row: &gimli::UnwindTableRow<gimli::EndianSlice<gimli::LittleEndian>>) -> Self {
+        let cfa = match row.cfa() {
+            gimli::CfaRule::RegisterAndOffset { register, offset } => {
+                CfaRule::RegisterAndOffset(register.0, *offset)
+            }
+            gimli::CfaRule::Expression(expression) => {
+                CfaRule::Expression(expression.0.slice().to_vec())
+            }
+        };
+        let registers = (0..=RETURN_ADDRESS)
+            .map(|register| {
+                let rule = match row.register(gimli::Register(register)) {
+                    gimli::RegisterRule::Undefined => RegisterRule::Undefined,
+                    gimli::RegisterRule::SameValue => RegisterRule::SameValue,
+                    gimli::RegisterRule::Offset(offset) => RegisterRule::Offset(offset),
+                    gimli::RegisterRule::ValOffset(offset) => RegisterRule::ValOffset(offset),
+                    gimli::RegisterRule::Register(other) => RegisterRule::Register(other.0),
+                    gimli::RegisterRule::Expression(expression) => {
+                        RegisterRule::Expression(expression.0.slice().to_vec())
+                    }
+                    gimli::RegisterRule::ValExpression(expression) => {
+                        RegisterRule::ValExpression(expression.0.slice().to_vec())
+                    }
+                    gimli::RegisterRule::Architectural => RegisterRule::SameValue,
+                };
+                (register, rule)
+            })
+            .collect();
+        UnwindRow { cfa, registers }
+    }
+}
+
+/// The CFI sections of one loaded object file.
+struct CfiModule {
+    path: String,
+    /// Difference between runtime and link-time addresses.
+    bias: usize,
+    /// Runtime address ranges the object is mapped at. Empty for the main executable, which
+    /// handles any pc that no shared library claims.
+    ranges: Vec<(usize, usize)>,
+    eh_frame: Vec<u8>,
+    eh_frame_addr: u64,
+    debug_frame: Vec<u8>,
+    text_addr: u64,
+}
+
+impl CfiModule {
+    fn load(path: &str, bias: usize, ranges: Vec<(usize, usize)>) -> Option<CfiModule> {
+        let file = fs::File::open(path).ok()?;
+        let mmap = unsafe { memmap::Mmap::map(&file).ok()? };
+        let object = object::File::parse(&*mmap).ok()?;
+        let section_data = |name: &str| {
+            object
+                .section_data_by_name(name)
+                .map(|data| data.to_vec())
+                .unwrap_or_default()
+        };
+        let section_addr = |name: &str| {
+            object
+                .section_by_name(name)
+                .map(|section| section.address())
+                .unwrap_or(0)
+        };
+        Some(CfiModule {
+            path: path.to_string(),
+            bias,
+            ranges,
+            eh_frame: section_data(".eh_frame"),
+            eh_frame_addr: section_addr(".eh_frame"),
+            debug_frame: section_data(".debug_frame"),
+            text_addr: section_addr(".text"),
+        })
+    }
+
+    fn contains(&self, pc: usize) -> bool {
+        self.ranges
+            .iter()
+            .any(|(start, end)| *start <= pc && pc < *end)
+    }
+
+    /// Finds the unwind rules for a runtime pc, preferring .eh_frame over .debug_frame.
+    fn find_row(&self, pc: usize) -> Option<UnwindRow> {
+        let address = pc.checked_sub(self.bias)? as u64;
+        let bases = gimli::BaseAddresses::default()
+            .set_eh_frame(self.eh_frame_addr)
+            .set_text(self.text_addr);
+        let mut ctx = gimli::UninitializedUnwindContext::new();
+        if !self.eh_frame.is_empty() {
+            let eh_frame = gimli::EhFrame::new(&self.eh_frame, gimli::LittleEndian);
+            if let Ok(row) = eh_frame.unwind_info_for_address(
+                &bases,
+                &mut ctx,
+                address,
+                gimli::EhFrame::cie_from_offset,
+            ) {
+                return Some(UnwindRow::from_gimli(&row));
+            }
+        }
+        if !self.debug_frame.is_empty() {
+            let mut debug_frame = gimli::DebugFrame::new(&self.debug_frame, gimli::LittleEndian);
+            debug_frame.set_address_size(8);
+            if let Ok(row) = debug_frame.unwind_info_for_address(
+                &bases,
+                &mut ctx,
+                address,
+                gimli::DebugFrame::cie_from_offset,
+            ) {
+                return Some(UnwindRow::from_gimli(&row));
+            }
+        }
+        None
+    }
+}
+
+/// Returns the lowest address any PT_LOAD segment of an object file is linked at.
+fn link_base(path: &str) -> Option<usize> {
+    let file = fs::File::open(path).ok()?;
+    let mmap = unsafe { memmap::Mmap::map(&file).ok()? };
+    let object = object::File::parse(&*mmap).ok()?;
+    let base = object.segments().map(|segment| segment.address()).min()?;
+    // Mappings start on a page boundary
+    Some(base as usize & !0xfff)
+}
+
+pub struct Unwinder {
+    target: String,
+    modules: Vec<CfiModule>,
+}
+
+impl Unwinder {
+    /// Creates an unwinder that knows about the CFI of the target executable. Shared libraries
+    /// are picked up by `refresh` once the inferior has mapped them.
+    pub fn new(target: &str) -> Unwinder {
+        Unwinder {
+            target: fs::canonicalize(target)
+                .map(|path| path.to_string_lossy().to_string())
+                .unwrap_or_else(|_| target.to_string()),
+            modules: CfiModule::load(target, 0, Vec::new()).into_iter().collect(),
+        }
+    }
+
+    /// Moves the executable's CFI to where it was loaded, `bias` bytes from its link-time
+    /// addresses.
+    pub fn set_load_bias(&mut self, bias: usize) {
+        if let Some(module) = self
+            .modules
+            .iter_mut()
+            .find(|module| module.ranges.is_empty())
+        {
+            module.bias = bias;
+        }
+    }
+
+    /// Loads the CFI of any shared object the inferior has mapped since the last refresh.
+    pub fn refresh(&mut self, pid: Pid) {
+        if let Ok(maps) = memory_map::read_maps(pid) {
+            self.load_modules(&maps);
+        }
+    }
+
+    /// Brings the loaded modules in line with a memory map, e.g. one read from a core dump.
+    pub fn load_modules(&mut self, maps: &[Mapping]) {
+        // Forget modules that have been unmapped (e.g. after an exec)
+        self.modules.retain(|module| {
+            module.ranges.is_empty()
+                || maps
+                    .iter()
+                    .any(|mapping| mapping.path.as_deref() == Some(module.path.as_str()))
+        });
+        for mapping in maps.iter().filter(|mapping| mapping.is_file()) {
+            let path = mapping.path.as_ref().unwrap();
+            if path == &self.target || self.modules.iter().any(|module| &module.path == path) {
+                continue;
+            }
+            let first = match maps
+                .iter()
+                .find(|other| other.path == mapping.path && other.offset == 0)
+            {
+                Some(first) => first,
+                None => continue,
+            };
+            let bias = match link_base(path) {
+                Some(base) => first.start.wrapping_sub(base),
+                None => continue,
+            };
+            let ranges = maps
+                .iter()
+                .filter(|other| other.path == mapping.path)
+                .map(|other| (other.start, other.end))
+                .collect();
+            if let Some(module) = CfiModule::load(path, bias, ranges) {
+                self.modules.push(module);
+            }
+        }
+    }
+
+    /// Returns the file name of the object containing `pc`, if it's a shared library.
+    pub fn module_name(&self, pc: usize) -> Option<String> {
+        let module = self.modules.iter().find(|module| module.contains(pc))?;
+        Path::new(&module.path)
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+    }
+
+    fn find_row(&self, pc: usize) -> Option<UnwindRow> {
+        let module = self
+            .modules
+            .iter()
+            .find(|module| module.contains(pc))
+            .or_else(|| self.modules.iter().find(|module| module.ranges.is_empty()))?;
+        module.find_row(pc)
+    }
+
+    /// Walks the stack starting from `regs`, returning the innermost frame first.
+    pub fn backtrace(&self, regs: libc::user_regs_struct, read_memory: ReadMemory) -> Vec<Frame> {
+        let mut frames: Vec<Frame> = Vec::new();
+        let mut regs = regs;
+        while frames.len() < MAX_FRAMES {
+            let pc = regs.rip as usize;
+            let frame_index = frames.len();
+            let row = self.find_row(if frame_index == 0 { pc } else { pc - 1 });
+            let caller = match &row {
+                Some(row) => self.unwind_with_row(&regs, row, read_memory),
+                None => unwind_with_frame_pointer(&regs, read_memory),
+            };
+            let (cfa, caller) = match caller {
+                Some((cfa, caller)) => (cfa, caller),
+                None => {
+                    frames.push(Frame {
+                        pc,
+                        cfa: regs.rsp as usize,
+                        regs,
+                    });
+                    break;
+                }
+            };
+            // The stack grows down, so each caller's CFA must be above its callee's
+            if let Some(prev) = frames.last() {
+                if cfa <= prev.cfa {
+                    break;
+                }
+            }
+            frames.push(Frame { pc, cfa, regs });
+            if caller.rip == 0 {
+                break;
+            }
+            regs = caller;
+        }
+        frames
+    }
+
+    /// Returns the CFA of the innermost frame described by `regs`.
+    pub fn cfa(&self, regs: &libc::user_regs_struct, read_memory: ReadMemory) -> Option<usize> {
+        match self.find_row(regs.rip as usize) {
+            Some(row) => self.compute_cfa(regs, &row, read_memory),
+            None => Some(regs.rbp as usize + 16),
+        }
+    }
+
+    fn compute_cfa(
+        &self,
+        regs: &libc::user_regs_struct,
+        row: &UnwindRow,
+        read_memory: ReadMemory,
+    ) -> Option<usize> {
+        match &row.cfa {
+            CfaRule::RegisterAndOffset(register, offset) => {
+                let value = dwarf_expr::dwarf_register(regs, *register)?;
+                Some(value.wrapping_add(*offset as u64) as usize)
+            }
+            CfaRule::Expression(expression) => {
+                // CFA expressions work from registers, not static addresses
+                let ctx = EvalContext {
+                    regs,
+                    cfa: None,
+                    frame_base: None,
+                    read_memory,
+                    load_bias: 0,
+                };
+                match dwarf_expr::evaluate(expression, CFI_ENCODING, &ctx)
+                    .ok()?
+                    .first()?
+                    .location
+                {
+                    PieceLocation::Memory(addr) => Some(addr),
+                    _ => None,
+                }
+            }
+        }
+    }
+
+    /// Applies an unwind row to recover the CFA and the caller's registers. Returns None if this
+    /// is the outermost frame.
+    fn unwind_with_row(
+        &self,
+        regs: &libc::user_regs_struct,
+        row: &UnwindRow,
+        read_memory: ReadMemory,
+    ) -> Option<(usize, libc::user_regs_struct)> {
+        let cfa = self.compute_cfa(regs, row, read_memory)?;
+        let ctx = EvalContext {
+            regs,
+            cfa: Some(cfa),
+            frame_base: None,
+            read_memory,
+            load_bias: 0,
+        };
+        let read_word = |addr: usize| -> Option<u64> {
+            let bytes = read_memory(addr, 8).ok()?;
+            let mut raw = [0u8; 8];
+            raw.copy_from_slice(&bytes[..8]);
+            Some(u64::from_le_bytes(raw))
+        };
+        let mut caller = *regs;
+        for (register, rule) in &row.registers {
+            let value = match rule {
+                // The x86-64 ABI marks the return address undefined in the outermost frame
+                RegisterRule::Undefined if *register == RETURN_ADDRESS => return None,
+                RegisterRule::Undefined | RegisterRule::SameValue => continue,
+                RegisterRule::Offset(offset) => read_word((cfa as i64 + offset) as usize)?,
+                RegisterRule::ValOffset(offset) => (cfa as i64 + offset) as u64,
+                RegisterRule::Register(other) => dwarf_expr::dwarf_register(regs, *other)?,
+                RegisterRule::Expression(expression) => {
+                    let pieces = dwarf_expr::evaluate_with_stack(
+                        expression,
+                        CFI_ENCODING,
+                        &ctx,
+                        vec![cfa as u64],
+                    )
+                    .ok()?;
+                    match pieces.first()?.location {
+                        PieceLocation::Memory(addr) => read_word(addr)?,
+                        _ => return None,
+                    }
+                }
+                RegisterRule::ValExpression(expression) => {
+                    let pieces = dwarf_expr::evaluate_with_stack(
+                        expression,
+                        CFI_ENCODING,
+                        &ctx,
+                        vec![cfa as u64],
+                    )
+                    .ok()?;
+                    match pieces.first()?.location {
+                        PieceLocation::Memory(value) => value as u64,
+                        PieceLocation::Value(value) => value,
+                        _ => return None,
+                    }
+                }
+            };
+            dwarf_expr::set_dwarf_register(&mut caller, *register, value);
+        }
+        caller.rsp = cfa as u64;
+        Some((cfa, caller))
+    }
+}
+
+/// Unwinds one frame by following the %rbp chain, for code without CFI.
+fn unwind_with_frame_pointer(
+    regs: &libc::user_regs_struct,
+    read_memory: ReadMemory,
+) -> Option<(usize, libc::user_regs_struct)> {
+    let rbp = regs.rbp as usize;
+    if rbp == 0 {
+        return None;
+    }
+    let bytes = read_memory(rbp, 16).ok()?;
+    let mut saved_rbp = [0u8; 8];
+    let mut ret_addr = [0u8; 8];
+    saved_rbp.copy_from_slice(&bytes[..8]);
+    ret_addr.copy_from_slice(&bytes[8..16]);
+    let mut caller = *regs;
+    caller.rbp = u64::from_le_bytes(saved_rbp);
+    caller.rip = u64::from_le_bytes(ret_addr);
+    caller.rsp = (rbp + 16) as u64;
+    Some((rbp + 16, caller))
+}
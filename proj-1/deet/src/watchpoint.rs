@@ -0,0 +1,151 @@
+//! Hardware watchpoints. x86-64 has four debug address registers (DR0-DR3), each of which can
+//! watch up to 8 naturally aligned bytes. DR7 says which of them are armed and for what kind of
+//! access, and DR6 reports which one fired.
+
+use crate::dwarf_data::Type;
+use crate::inferior::Inferior;
+
+/// Number of debug address registers, and so of watchpoints that can exist at once.
+pub const MAX_WATCHPOINTS: usize = 4;
+
+const DR6: usize = 6;
+const DR7: usize = 7;
+/// DR7 access condition meaning "trap on data writes".
+const DR7_WRITE: usize = 0b01;
+
+pub struct Watchpoint {
+    pub id: usize,
+    /// What the user asked to watch, e.g. "count" or "*0x601040".
+    pub expression: String,
+    pub addr: usize,
+    pub len: usize,
+    /// Type used to format the value. None when watching a raw address.
+    pub var_type: Option<Type>,
+    /// Contents as of the last time we looked, or None if the inferior hasn't started yet.
+    pub old_value: Option<Vec<u8>>,
+    pub hit_count: usize,
+    /// Which of DR0-DR3 holds the address.
+    slot: usize,
+}
+
+/// Returns true if the debug registers can watch `len` bytes at `addr`.
+pub fn can_watch(addr: usize, len: usize) -> bool {
+    matches!(len, 1 | 2 | 4 | 8) && addr % len == 0
+}
+
+fn dr7_len_bits(len: usize) -> usize {
+    match len {
+        1 => 0b00,
+        2 => 0b01,
+        8 => 0b10,
+        _ => 0b11,
+    }
+}
+
+pub struct WatchpointTable {
+    watchpoints: Vec<Watchpoint>,
+}
+
+impl WatchpointTable {
+    pub fn new() -> WatchpointTable {
+        WatchpointTable {
+            watchpoints: Vec::new(),
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Watchpoint> {
+        self.watchpoints.iter()
+    }
+
+    pub fn get(&self, id: usize) -> Option<&Watchpoint> {
+        self.watchpoints.iter().find(|wp| wp.id == id)
+    }
+
+    pub fn get_mut(&mut self, id: usize) -> Option<&mut Watchpoint> {
+        self.watchpoints.iter_mut().find(|wp| wp.id == id)
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.watchpoints.len() == MAX_WATCHPOINTS
+    }
+
+    /// Adds a watchpoint on `len` bytes at `addr`, arming it in the inferior if one is running.
+    /// The caller must check `is_full` and `can_watch` first.
+    pub fn add(
+        &mut self,
+        id: usize,
+        expression: String,
+        addr: usize,
+        len: usize,
+        var_type: Option<Type>,
+        inferior: Option<&mut Inferior>,
+    ) -> Result<(), nix::Error> {
+        let slot = (0..MAX_WATCHPOINTS)
+            .find(|slot| self.watchpoints.iter().all(|wp| wp.slot != *slot))
+            .unwrap();
+        self.watchpoints.push(Watchpoint {
+            id,
+            expression,
+            addr,
+            len,
+            var_type,
+            old_value: None,
+            hit_count: 0,
+            slot,
+        });
+        match inferior {
+            Some(inferior) => self.install(inferior),
+            None => Ok(()),
+        }
+    }
+
+    pub fn delete(&mut self, id: usize, inferior: Option<&mut Inferior>) -> Result<(), nix::Error> {
+        self.watchpoints.retain(|wp| wp.id != id);
+        match inferior {
+            Some(inferior) => inferior.set_debug_register(DR7, self.dr7()),
+            None => Ok(()),
+        }
+    }
+
+    /// Deletes every watchpoint.
+    pub fn clear(&mut self, inferior: Option<&mut Inferior>) -> Result<(), nix::Error> {
+        self.watchpoints.clear();
+        match inferior {
+            Some(inferior) => inferior.set_debug_register(DR7, 0),
+            None => Ok(()),
+        }
+    }
+
+    /// Programs the debug registers of a (newly started) inferior with every watchpoint, and
+    /// records the current contents of the watched memory.
+    pub fn install(&mut self, inferior: &mut Inferior) -> Result<(), nix::Error> {
+        for wp in self.watchpoints.iter_mut() {
+            inferior.set_debug_register(wp.slot, wp.addr)?;
+            wp.old_value = Some(inferior.read_memory(wp.addr, wp.len)?);
+        }
+        inferior.set_debug_register(DR7, self.dr7())
+    }
+
+    /// Returns the IDs of the watchpoints that have fired since the last call, according to DR6.
+    pub fn triggered(&self, inferior: &mut Inferior) -> Result<Vec<usize>, nix::Error> {
+        let dr6 = inferior.debug_register(DR6)?;
+        // The processor never clears DR6 itself
+        inferior.set_debug_register(DR6, 0)?;
+        Ok(self
+            .watchpoints
+            .iter()
+            .filter(|wp| dr6 & (1 << wp.slot) != 0)
+            .map(|wp| wp.id)
+            .collect())
+    }
+
+    fn dr7(&self) -> usize {
+        let mut dr7 = 0;
+        for wp in self.watchpoints.iter() {
+            // Local enable bit, then the access condition and length fields
+            dr7 |= 1 << (2 * wp.slot);
+            dr7 |= (DR7_WRITE | dr7_len_bits(wp.len) << 2) << (16 + 4 * wp.slot);
+        }
+        dr7
+    }
+}